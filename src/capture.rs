@@ -0,0 +1,121 @@
+//! 读取被拦截的响应体，响应体过大、超时或者出错时不再缓存，剩余部分原样转发给客户端
+
+use std::{fmt, time::Duration};
+
+use http::{HeaderMap, Response};
+use http_body_util::{BodyExt, Full};
+use hudsucker::{futures::stream, hyper::body::Bytes};
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureLimits {
+    /// 最多缓存多少字节的响应体，解压后的数据同样受此限制
+    pub max_body_size: usize,
+    /// 读取整个响应体的最长时间
+    pub timeout: Duration,
+}
+
+impl Default for CaptureLimits {
+    fn default() -> Self {
+        Self {
+            max_body_size: 16 * 1024 * 1024,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// 读取响应体时的错误，同样适用于所有规则，显示时需要由调用方加上规则名称
+#[derive(Debug)]
+pub enum CaptureError {
+    TooLarge { limit: usize },
+    Timeout { timeout: Duration },
+    Body(hudsucker::Error),
+    Decode(hudsucker::Error),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { limit } => {
+                write!(f, "响应数据超过了 {} KiB 的大小限制", limit / 1024)
+            }
+            Self::Timeout { timeout } => {
+                write!(f, "接收响应数据超时（{} 秒）", timeout.as_secs())
+            }
+            Self::Body(err) => write!(f, "接收响应数据失败：{err}"),
+            Self::Decode(err) => write!(f, "解压响应数据失败：{err}"),
+        }
+    }
+}
+
+/// 把已经读取的部分和剩余的响应体重新拼成一个响应体
+fn prefixed(prefix: Vec<u8>, rest: hudsucker::Body) -> hudsucker::Body {
+    use hudsucker::futures::StreamExt;
+
+    let prefix = stream::iter([Ok::<_, hudsucker::Error>(Bytes::from(prefix))]);
+    hudsucker::Body::from_stream(prefix.chain(rest.into_data_stream()))
+}
+
+/// 在限制范围内读取整个响应体，失败时返回错误以及可以原样转发的响应体
+pub async fn read_body(
+    mut body: hudsucker::Body,
+    limits: &CaptureLimits,
+) -> Result<Bytes, (CaptureError, hudsucker::Body)> {
+    let deadline = Instant::now() + limits.timeout;
+    let mut data = Vec::new();
+
+    loop {
+        let frame = match tokio::time::timeout_at(deadline, body.frame()).await {
+            Ok(Some(Ok(frame))) => frame,
+            Ok(Some(Err(err))) => {
+                // 让客户端收到同样的错误
+                let failed = stream::iter([
+                    Ok(Bytes::from(data)),
+                    Err(hudsucker::Error::from(std::io::Error::other(
+                        err.to_string(),
+                    ))),
+                ]);
+                return Err((
+                    CaptureError::Body(err),
+                    hudsucker::Body::from_stream(failed),
+                ));
+            }
+            Ok(None) => return Ok(Bytes::from(data)),
+            Err(_) => {
+                let err = CaptureError::Timeout {
+                    timeout: limits.timeout,
+                };
+                return Err((err, prefixed(data, body)));
+            }
+        };
+
+        // 忽略 trailers，分数接口不会用到
+        if let Ok(chunk) = frame.into_data() {
+            data.extend_from_slice(&chunk);
+            if data.len() > limits.max_body_size {
+                let err = CaptureError::TooLarge {
+                    limit: limits.max_body_size,
+                };
+                return Err((err, prefixed(data, body)));
+            }
+        }
+    }
+}
+
+/// 按照响应头中的 `Content-Encoding` 解压已经完整读取的响应体
+pub async fn decode_body(
+    headers: &HeaderMap,
+    raw: Bytes,
+    limits: &CaptureLimits,
+) -> Result<Bytes, CaptureError> {
+    let mut res = Response::new(hudsucker::Body::from(Full::new(raw)));
+    *res.headers_mut() = headers.clone();
+
+    let res = hudsucker::decode_response(res).map_err(CaptureError::Decode)?;
+    read_body(res.into_body(), limits)
+        .await
+        .map_err(|(err, _)| match err {
+            CaptureError::Body(err) => CaptureError::Decode(err),
+            err => err,
+        })
+}
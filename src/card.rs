@@ -0,0 +1,441 @@
+//! 成绩卡片，把计入评分的谱面画成一张图片，方便分享到群聊
+//!
+//! 文字使用内置的 GNU Unifont 点阵字体绘制，覆盖中文和日文。PNG 由程序自己光栅化，
+//! SVG 中的文字也转换成路径，两种格式都不依赖系统字体，可以完全离线生成
+
+use std::{
+    fmt::{self, Write},
+    io,
+    path::Path,
+};
+
+use crate::{
+    history::{self, CaptureRecord},
+    rating::RatingReport,
+    songs_score,
+};
+
+const WIDTH: u32 = 1000;
+const PADDING: u32 = 24;
+const HEADER_HEIGHT: u32 = 120;
+const ROW_HEIGHT: u32 = 64;
+const FOOTER_HEIGHT: u32 = 48;
+/// 歌曲名称和评分所在列的起始位置
+const TITLE_X: u32 = 96;
+const RATING_WIDTH: u32 = 160;
+/// 点阵字体的高度，半角字符宽 8 像素，全角字符宽 16 像素
+const GLYPH_HEIGHT: u32 = 16;
+
+const BACKGROUND: Color = Color(0xfb, 0xf4, 0xe6);
+const HEADER: Color = Color(0xe8, 0x4a, 0x27);
+const ROW_ALTERNATE: Color = Color(0xf3, 0xe8, 0xd2);
+const TEXT: Color = Color(0x33, 0x2a, 0x22);
+const MUTED: Color = Color(0x8a, 0x7f, 0x72);
+const WHITE: Color = Color(0xff, 0xff, 0xff);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardFormat {
+    Png,
+    Svg,
+}
+
+impl CardFormat {
+    /// 根据文件扩展名判断图片格式
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(Self::Png),
+            "svg" => Some(Self::Svg),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Color(u8, u8, u8);
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+fn difficulty_color(level: i64) -> Color {
+    match level {
+        1 => Color(0xf5, 0x6b, 0x2a),
+        2 => Color(0x7c, 0xb3, 0x42),
+        3 => Color(0x4f, 0x8f, 0xa8),
+        4 => Color(0xd0, 0x30, 0x8f),
+        5 => Color(0x6a, 0x3b, 0xd1),
+        _ => MUTED,
+    }
+}
+
+fn crown_color(crown: &str) -> Color {
+    match crown {
+        "全良" => Color(0xd9, 0x4f, 0xa0),
+        "全连" => Color(0xd4, 0x9a, 0x00),
+        _ => TEXT,
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Shape {
+    Rect {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        color: Color,
+    },
+    /// 以左上角为原点的文字，`scale` 为点阵的放大倍数
+    Text {
+        x: u32,
+        y: u32,
+        scale: u32,
+        color: Color,
+        text: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Card {
+    width: u32,
+    height: u32,
+    shapes: Vec<Shape>,
+}
+
+impl Card {
+    /// 根据一次捕获和它的评分结果排版，只包括计入综合评分的谱面
+    pub fn new(record: &CaptureRecord, report: &RatingReport) -> Self {
+        let counted = report
+            .contributions
+            .iter()
+            .take_while(|x| x.counted)
+            .collect::<Vec<_>>();
+        let height = HEADER_HEIGHT + counted.len().max(1) as u32 * ROW_HEIGHT + FOOTER_HEIGHT;
+        let mut card = Self {
+            width: WIDTH,
+            height,
+            shapes: Vec::new(),
+        };
+
+        card.rect(0, 0, WIDTH, height, BACKGROUND);
+        card.rect(0, 0, WIDTH, HEADER_HEIGHT, HEADER);
+        let player = format!("用户 {}", record.scores.userid);
+        let rating = format!("{:.2}", report.rating);
+        let rating_width = text_width(&rating, 3);
+        let player = truncate(&player, 2, WIDTH - PADDING * 3 - rating_width);
+        card.text(PADDING, 24, 2, WHITE, player);
+        card.text(
+            PADDING,
+            80,
+            1,
+            WHITE,
+            format!(
+                "捕获时间 {}  评分公式 {}",
+                history::format_timestamp(record.captured_at),
+                report.formula
+            ),
+        );
+        card.text_right(WIDTH - PADDING, 16, 3, WHITE, rating);
+        card.text_right(
+            WIDTH - PADDING,
+            80,
+            1,
+            WHITE,
+            format!("最好的 {} 个谱面的平均值", report.best_n),
+        );
+
+        if counted.is_empty() {
+            card.text(
+                PADDING,
+                HEADER_HEIGHT + 24,
+                1,
+                MUTED,
+                "没有可计入评分的谱面",
+            );
+        }
+        for (index, contribution) in counted.iter().enumerate() {
+            let y = HEADER_HEIGHT + index as u32 * ROW_HEIGHT;
+            if index % 2 == 1 {
+                card.rect(0, y, WIDTH, ROW_HEIGHT, ROW_ALTERNATE);
+            }
+            card.rect(0, y, 8, ROW_HEIGHT, difficulty_color(contribution.level));
+
+            card.text(PADDING, y + 16, 2, MUTED, (index + 1).to_string());
+            let title_width = WIDTH - TITLE_X - RATING_WIDTH - PADDING;
+            let title = truncate(&contribution.song_name, 2, title_width);
+            card.text(TITLE_X, y + 8, 2, TEXT, title);
+
+            let stars = contribution
+                .stars
+                .map_or_else(String::new, |stars| format!(" ★{}", stars));
+            let details = format!(
+                "{}{}  {}  {}",
+                songs_score::difficulty_name(contribution.level),
+                stars,
+                contribution.high_score,
+                songs_score::rank_name(contribution.best_score_rank),
+            );
+            let crown_x = TITLE_X + text_width(&details, 1) + 16;
+            card.text(TITLE_X, y + 42, 1, MUTED, details);
+            card.text(
+                crown_x,
+                y + 42,
+                1,
+                crown_color(contribution.crown),
+                contribution.crown,
+            );
+
+            card.text_right(
+                WIDTH - PADDING,
+                y + 16,
+                2,
+                HEADER,
+                format!("{:.2}", contribution.rating),
+            );
+        }
+
+        card.text(
+            PADDING,
+            height - FOOTER_HEIGHT + 16,
+            1,
+            MUTED,
+            "由 taiko-score-getter 生成",
+        );
+        card
+    }
+
+    fn rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: Color) {
+        self.shapes.push(Shape::Rect {
+            x,
+            y,
+            width,
+            height,
+            color,
+        });
+    }
+
+    fn text(&mut self, x: u32, y: u32, scale: u32, color: Color, text: impl Into<String>) {
+        self.shapes.push(Shape::Text {
+            x,
+            y,
+            scale,
+            color,
+            text: text.into(),
+        });
+    }
+
+    /// 右对齐的文字，`right` 为文字右边缘的位置
+    fn text_right(
+        &mut self,
+        right: u32,
+        y: u32,
+        scale: u32,
+        color: Color,
+        text: impl Into<String>,
+    ) {
+        let text = text.into();
+        let x = right.saturating_sub(text_width(&text, scale));
+        self.text(x, y, scale, color, text);
+    }
+
+    pub fn encode(&self, format: CardFormat) -> io::Result<Vec<u8>> {
+        match format {
+            CardFormat::Png => self.to_png(),
+            CardFormat::Svg => Ok(self.to_svg().into_bytes()),
+        }
+    }
+
+    pub fn to_png(&self) -> io::Result<Vec<u8>> {
+        let mut canvas = Canvas {
+            width: self.width,
+            height: self.height,
+            pixels: vec![0; (self.width * self.height * 3) as usize],
+        };
+        for shape in &self.shapes {
+            match shape {
+                Shape::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                    color,
+                } => canvas.fill(*x, *y, *width, *height, *color),
+                Shape::Text {
+                    x,
+                    y,
+                    scale,
+                    color,
+                    text,
+                } => {
+                    for (dx, dy, len) in glyph_runs(text) {
+                        canvas.fill(x + dx * scale, y + dy * scale, len * scale, *scale, *color);
+                    }
+                }
+            }
+        }
+
+        let mut output = Vec::new();
+        let mut encoder = png::Encoder::new(&mut output, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer
+            .write_image_data(&canvas.pixels)
+            .map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)?;
+        Ok(output)
+    }
+
+    pub fn to_svg(&self) -> String {
+        let mut svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}" shape-rendering="crispEdges">"#,
+            self.width, self.height
+        );
+        svg.push('\n');
+        for shape in &self.shapes {
+            match shape {
+                Shape::Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                    color,
+                } => {
+                    writeln!(
+                        svg,
+                        r#"<rect x="{x}" y="{y}" width="{width}" height="{height}" fill="{color}"/>"#
+                    )
+                    .unwrap();
+                }
+                Shape::Text {
+                    x,
+                    y,
+                    scale,
+                    color,
+                    text,
+                } => {
+                    // 每一段连续的点转换成一个矩形，保留原文供读屏软件和搜索使用
+                    let mut path = String::new();
+                    for (dx, dy, len) in glyph_runs(text) {
+                        write!(
+                            path,
+                            "M{} {}h{}v{}h-{}z",
+                            x + dx * scale,
+                            y + dy * scale,
+                            len * scale,
+                            scale,
+                            len * scale
+                        )
+                        .unwrap();
+                    }
+                    writeln!(
+                        svg,
+                        r#"<path fill="{}" aria-label="{}" d="{}"/>"#,
+                        color,
+                        escape_xml(text),
+                        path
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, color: Color) {
+        let right = (x + width).min(self.width);
+        let bottom = (y + height).min(self.height);
+        for py in y..bottom {
+            for px in x..right {
+                let offset = ((py * self.width + px) * 3) as usize;
+                self.pixels[offset..offset + 3].copy_from_slice(&[color.0, color.1, color.2]);
+            }
+        }
+    }
+}
+
+/// 点阵字体中没有的字符用问号代替
+fn glyph(c: char) -> &'static unifont::Glyph {
+    unifont::get_glyph(c)
+        .or_else(|| unifont::get_glyph('?'))
+        .expect("点阵字体中没有问号")
+}
+
+fn text_width(text: &str, scale: u32) -> u32 {
+    text.chars()
+        .map(|c| glyph(c).get_width() as u32 * scale)
+        .sum()
+}
+
+/// 超过最大宽度时截断并加上省略号
+fn truncate(text: &str, scale: u32, max_width: u32) -> String {
+    if text_width(text, scale) <= max_width {
+        return text.to_string();
+    }
+
+    let max_width = max_width.saturating_sub(text_width("…", scale));
+    let mut width = 0;
+    let mut output = String::new();
+    for c in text.chars() {
+        width += text_width(c.encode_utf8(&mut [0; 4]), scale);
+        if width > max_width {
+            break;
+        }
+        output.push(c);
+    }
+    output.push('…');
+    output
+}
+
+/// 文字中每一行连续的点，返回相对于文字左上角的 `(x, y, 长度)`，单位为点阵的像素
+fn glyph_runs(text: &str) -> Vec<(u32, u32, u32)> {
+    let mut runs = Vec::new();
+    let mut offset = 0;
+    for c in text.chars() {
+        let glyph = glyph(c);
+        let width = glyph.get_width();
+        for y in 0..GLYPH_HEIGHT as usize {
+            let mut x = 0;
+            while x < width {
+                if !glyph.get_pixel(x, y) {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < width && glyph.get_pixel(x, y) {
+                    x += 1;
+                }
+                runs.push((offset + start as u32, y as u32, (x - start) as u32));
+            }
+        }
+        offset += width as u32;
+    }
+    runs
+}
+
+/// 转义 SVG 和 HTML 中的特殊字符
+pub fn escape_xml(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            _ => output.push(c),
+        }
+    }
+    output
+}
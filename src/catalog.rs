@@ -0,0 +1,154 @@
+//! 离线歌曲目录，合并每次捕获到的 `SongDetail`，保存在配置目录的 `songs.json` 中
+//!
+//! 导出和报告中的歌曲信息都以这里为准
+
+use std::{collections::BTreeMap, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::songs_score::{self, ScoreInfo, SongDetail};
+
+pub const CATALOG_FILE_NAME: &str = "songs.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogSong {
+    #[serde(flatten)]
+    pub detail: SongDetail,
+    /// 第一次出现在捕获数据中的时间，Unix 时间戳（秒）
+    pub first_seen: i64,
+    /// 最后一次出现在捕获数据中的时间
+    pub last_seen: i64,
+}
+
+impl CatalogSong {
+    fn matches(&self, query: &str) -> bool {
+        [&self.detail.song_name, &self.detail.song_name_jp]
+            .iter()
+            .any(|name| name.to_lowercase().contains(query))
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Catalog {
+    /// 以歌曲编号（`song_no`）为键
+    songs: BTreeMap<i64, CatalogSong>,
+}
+
+impl Catalog {
+    /// 读取歌曲目录，文件不存在时返回空目录
+    pub async fn load(config_dir: &Path) -> io::Result<Self> {
+        match tokio::fs::read(config_dir.join(CATALOG_FILE_NAME)).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(io::Error::other),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn save(&self, config_dir: &Path) -> io::Result<()> {
+        tokio::fs::create_dir_all(config_dir).await?;
+        let data = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        tokio::fs::write(config_dir.join(CATALOG_FILE_NAME), data).await
+    }
+
+    /// 合并一次捕获中的歌曲信息，已有的歌曲使用较新的数据更新，返回新增歌曲的编号
+    ///
+    /// 导入以前的记录时捕获时间可能早于目录中的数据，这时只更新出现的时间
+    pub fn merge(&mut self, data: &songs_score::Data, captured_at: i64) -> Vec<i64> {
+        let mut added = Vec::new();
+
+        for item in &data.score_info {
+            let detail = &item.song_detail;
+            if detail.is_empty() {
+                continue;
+            }
+
+            match self.songs.get_mut(&item.song_no) {
+                Some(song) => {
+                    if captured_at >= song.last_seen {
+                        song.detail = detail.clone();
+                        song.last_seen = captured_at;
+                    }
+                    song.first_seen = song.first_seen.min(captured_at);
+                }
+                None => {
+                    self.songs.insert(
+                        item.song_no,
+                        CatalogSong {
+                            detail: detail.clone(),
+                            first_seen: captured_at,
+                            last_seen: captured_at,
+                        },
+                    );
+                    added.push(item.song_no);
+                }
+            }
+        }
+
+        added
+    }
+
+    /// 成绩对应的歌曲信息，目录中有这首歌时以目录为准，否则使用成绩自带的歌曲信息
+    ///
+    /// 曲名、分类和星级等歌曲信息都应该通过这里读取，导入的记录可能没有歌曲信息
+    pub fn detail_for<'a>(catalog: Option<&'a Self>, item: &'a ScoreInfo) -> &'a SongDetail {
+        catalog
+            .and_then(|catalog| catalog.get(item.song_no))
+            .map_or(&item.song_detail, |song| &song.detail)
+    }
+
+    /// 用目录中的数据补全没有歌曲信息的成绩，例如从同步接口的数组导入的成绩
+    pub fn fill_details(&self, data: &mut songs_score::Data) {
+        for item in &mut data.score_info {
+            if !item.song_detail.is_empty() {
+                continue;
+            }
+            if let Some(song) = self.get(item.song_no) {
+                item.song_detail = song.detail.clone();
+            }
+        }
+    }
+
+    pub fn get(&self, song_no: i64) -> Option<&CatalogSong> {
+        self.songs.get(&song_no)
+    }
+
+    /// 按歌曲编号从小到大遍历所有歌曲
+    pub fn songs(&self) -> impl Iterator<Item = (i64, &CatalogSong)> {
+        self.songs.iter().map(|(song_no, song)| (*song_no, song))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty()
+    }
+
+    /// 按中文或日文曲名搜索，不区分大小写
+    pub fn search(&self, query: &str) -> Vec<(i64, &CatalogSong)> {
+        let query = query.trim().to_lowercase();
+        let mut songs = self
+            .songs
+            .iter()
+            .filter(|(_, song)| song.matches(&query))
+            .map(|(song_no, song)| (*song_no, song))
+            .collect::<Vec<_>>();
+        songs.sort_by_key(|(song_no, song)| (song.detail.sort, *song_no));
+        songs
+    }
+
+    /// 在 `since`（`YYYY-MM-DD`）当天及之后上线的歌曲，最新的在前
+    pub fn opened_since(&self, since: &str) -> Vec<(i64, &CatalogSong)> {
+        let mut songs = self
+            .songs
+            .iter()
+            .filter(|(_, song)| song.detail.open_day.as_str() >= since)
+            .map(|(song_no, song)| (*song_no, song))
+            .collect::<Vec<_>>();
+        songs.sort_by(|(a_no, a), (b_no, b)| {
+            b.detail
+                .open_day
+                .cmp(&a.detail.open_day)
+                .then(a_no.cmp(b_no))
+        });
+        songs
+    }
+}
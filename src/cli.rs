@@ -0,0 +1,792 @@
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+
+use crate::{
+    capture::CaptureLimits,
+    card::{Card, CardFormat},
+    catalog::Catalog,
+    config::Config,
+    goals::{Goal, GoalState},
+    history::{self, History},
+    import,
+    os::{self, CertStore, NoopCertStore, NoopProxy, ProxyConfigs, SystemCertStore},
+    query::{self, OutputFormat, ScoreQuery},
+    rating::{self, RatingFormula},
+    report,
+    rules::RuleSet,
+    songs_score,
+    stats::Stats,
+    targets,
+    upstream::UpstreamProxy,
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "太鼓达人成绩获取工具")]
+pub struct Cli {
+    /// 试运行，不会修改系统证书库和系统代理配置
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// 上游代理地址，例如 http://127.0.0.1:7890 或 socks5://127.0.0.1:7891，默认使用原有的系统代理
+    #[arg(long)]
+    pub upstream_proxy: Option<UpstreamProxy>,
+
+    /// 使用自动代理配置（PAC）代替全局代理，只有成绩相关的域名会经过本程序
+    #[arg(long)]
+    pub pac: bool,
+
+    /// 同时在指定端口上提供 SOCKS5 代理，供只支持 SOCKS5 的模拟器或分应用代理工具使用
+    #[arg(long, value_name = "PORT")]
+    pub socks5_port: Option<u16>,
+
+    /// 每条规则捕获的响应数据的最大大小（MiB），超过后不再解析，响应会原样转发
+    #[arg(long, value_name = "MIB")]
+    pub max_capture_size: Option<usize>,
+
+    /// 每条规则接收响应数据的超时时间（秒），超时后不再解析，响应会原样转发
+    #[arg(long, value_name = "SECONDS")]
+    pub capture_timeout: Option<u64>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Cli {
+    pub fn capture_limits(&self) -> CaptureLimits {
+        let mut limits = CaptureLimits::default();
+        if let Some(size) = self.max_capture_size {
+            limits.max_body_size = size * 1024 * 1024;
+        }
+        if let Some(timeout) = self.capture_timeout {
+            limits.timeout = Duration::from_secs(timeout);
+        }
+        limits
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 卸载证书并删除私钥文件，同时还原残留的系统代理配置
+    Uninstall,
+    /// 管理代理使用的 CA 证书
+    Cert {
+        #[command(subcommand)]
+        command: CertCommand,
+    },
+    /// 统计最近一次捕获的王冠、评价、准确率等数据
+    Stats {
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+    },
+    /// 根据最近一次捕获计算玩家评分，并列出每个谱面的评分
+    Rating {
+        /// 评分公式的版本，默认使用配置文件中的设置或者默认公式
+        #[arg(long, value_name = "VERSION")]
+        formula: Option<String>,
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+    },
+    /// 根据最近一次捕获，列出离下一个评价或王冠最近的谱面
+    Targets {
+        /// 最多列出的谱面数量
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        /// 以 JSON 格式输出
+        #[arg(long)]
+        json: bool,
+    },
+    /// 从文件导入分数数据到捕获记录中，已有的记录（用户 ID 和捕获时间相同）会被跳过
+    Import {
+        /// 鼓众广场分数接口的原始 JSON、同步接口的 14 列数组，或者本程序导出的 JSON 和 CSV 文件
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// 文件中没有用户 ID 时使用的用户 ID
+        #[arg(long)]
+        userid: Option<String>,
+    },
+    /// 查询捕获到的成绩
+    Scores {
+        #[command(subcommand)]
+        command: ScoresCommand,
+    },
+    /// 管理目标，并查看在最近一次捕获中的进度
+    Goals {
+        #[command(subcommand)]
+        command: Option<GoalsCommand>,
+    },
+    /// 把最近一次捕获中计入评分的谱面画成成绩卡片
+    Card {
+        /// 输出文件，根据扩展名生成 PNG 或 SVG 图片
+        output: PathBuf,
+        /// 评分公式的版本，默认使用配置文件中的设置或者默认公式
+        #[arg(long, value_name = "VERSION")]
+        formula: Option<String>,
+    },
+    /// 根据所有捕获记录生成个人进度报告，报告为一个独立的 HTML 文件
+    Report {
+        /// 输出文件
+        #[arg(default_value = "report.html")]
+        output: PathBuf,
+        /// 评分公式的版本，默认使用配置文件中的设置或者默认公式
+        #[arg(long, value_name = "VERSION")]
+        formula: Option<String>,
+    },
+    /// 查询从捕获数据中整理出的离线歌曲目录
+    Songs {
+        #[command(subcommand)]
+        command: SongsCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CertCommand {
+    /// 查看证书的名称、有效期以及存在的问题
+    Status,
+    /// 重新生成证书，并替换系统证书库中的旧证书
+    Rotate,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ScoresCommand {
+    /// 按难度、星级、分类、王冠、评价、分数、曲名和日期筛选成绩，并按任意字段排序
+    Query(ScoreQuery),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum GoalsCommand {
+    /// 列出所有目标以及在最近一次捕获中的进度（默认）
+    List,
+    /// 添加一个目标，例如 --name 魔王十星全连 --crown full-combo --level 4 --stars 10 --count 20
+    Add {
+        #[command(flatten)]
+        goal: Goal,
+    },
+    /// 删除通过命令添加的目标
+    Remove { name: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SongsCommand {
+    /// 按中文或日文曲名搜索歌曲
+    Search { keyword: String },
+    /// 列出指定日期之后上线的歌曲
+    New {
+        /// 日期，格式为 YYYY-MM-DD，默认为 30 天前
+        #[arg(long, value_name = "DATE", value_parser = crate::query::parse_date)]
+        since: Option<String>,
+    },
+}
+
+/// 有文件无法删除时返回失败的退出码
+pub async fn uninstall(dry_run: bool) -> ExitCode {
+    let config_dir = crate::get_config_dir();
+
+    let report = if dry_run {
+        os::uninstall(&config_dir, crate::LISTEN_ADDR, &NoopCertStore, &NoopProxy).await
+    } else {
+        let system_proxy = ProxyConfigs::new().await;
+        os::uninstall(
+            &config_dir,
+            crate::LISTEN_ADDR,
+            &SystemCertStore,
+            &system_proxy,
+        )
+        .await
+    };
+
+    if report.proxy_restored {
+        println!("已关闭残留的系统代理配置");
+    } else {
+        println!("没有发现残留的系统代理配置");
+    }
+
+    if report.removed_certs.is_empty() {
+        println!("系统证书库中没有找到 {}", os::CERT_NAME_PREFIX);
+    }
+    for cert_name in &report.removed_certs {
+        println!("已从系统证书库中移除 {}", cert_name);
+    }
+
+    if report.removed_files.is_empty() && report.failed_files.is_empty() {
+        println!("没有需要删除的证书文件");
+    }
+    for path in &report.removed_files {
+        println!("已删除 {}", path.to_string_lossy());
+    }
+    for (path, err) in &report.failed_files {
+        eprintln!("无法删除 {}：{}", path.to_string_lossy(), err);
+    }
+
+    if report.failed_files.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+pub async fn cert(command: CertCommand, rules: &RuleSet, dry_run: bool) -> ExitCode {
+    if dry_run {
+        cert_with(command, rules, &NoopCertStore).await
+    } else {
+        cert_with(command, rules, &SystemCertStore).await
+    }
+}
+
+async fn cert_with(command: CertCommand, rules: &RuleSet, cert_store: &impl CertStore) -> ExitCode {
+    let config_dir = crate::get_config_dir();
+
+    match command {
+        CertCommand::Status => {
+            let status = os::check_ca(&config_dir, &rules.hosts(), cert_store).await;
+
+            println!("证书文件：{}", config_dir.join("ca.cer").to_string_lossy());
+            if let Some(cert_name) = &status.cert_name {
+                println!("证书名称：{}", cert_name);
+            }
+            if let Some(not_after) = status.not_after {
+                println!(
+                    "有效期至：{:04}-{:02}-{:02}",
+                    not_after.year(),
+                    u8::from(not_after.month()),
+                    not_after.day()
+                );
+            }
+
+            if status.problems.is_empty() {
+                println!("证书状态：正常");
+            }
+            for problem in &status.problems {
+                println!("证书问题：{}", problem);
+            }
+            if status.needs_rotation() {
+                println!("下次启动时将自动重新生成证书，也可以执行 cert rotate 立即更换");
+            }
+        }
+        CertCommand::Rotate => {
+            let report = match os::rotate_ca(&config_dir, &rules.hosts(), cert_store).await {
+                Ok(report) => report,
+                Err(err) => {
+                    eprintln!("无法更换证书：{}", err);
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            for cert_name in &report.removed_certs {
+                println!("已从系统证书库中移除 {}", cert_name);
+            }
+            println!("已生成并安装新证书 {}", report.cert_name);
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+pub async fn songs(command: SongsCommand) {
+    let catalog = Catalog::load(&crate::get_config_dir())
+        .await
+        .expect("无法读取歌曲目录");
+    if catalog.is_empty() {
+        println!("歌曲目录为空，请先捕获一次分数数据");
+        return;
+    }
+
+    let songs = match &command {
+        SongsCommand::Search { keyword } => catalog.search(keyword),
+        SongsCommand::New { since } => {
+            let since = since.clone().unwrap_or_else(|| {
+                let date = (time::OffsetDateTime::now_utc() - time::Duration::days(30)).date();
+                format!(
+                    "{:04}-{:02}-{:02}",
+                    date.year(),
+                    u8::from(date.month()),
+                    date.day()
+                )
+            });
+            catalog.opened_since(&since)
+        }
+    };
+
+    if songs.is_empty() {
+        println!("没有找到符合条件的歌曲");
+    }
+    for (song_no, song) in songs {
+        let detail = &song.detail;
+        println!(
+            "{:>5}  {}  {} / {}  [{}]",
+            song_no, detail.open_day, detail.song_name, detail.song_name_jp, detail.family
+        );
+    }
+}
+
+/// 没有可用的捕获记录时把原因输出到标准错误，并返回失败的退出码
+pub async fn stats(json: bool) -> ExitCode {
+    let history = History::new(&crate::get_config_dir());
+    let record = match history.latest().await {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            eprintln!("还没有捕获记录，请先捕获一次分数数据");
+            return ExitCode::FAILURE;
+        }
+        Err(err) => {
+            eprintln!("无法读取捕获记录：{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let catalog = Catalog::load(&crate::get_config_dir()).await.ok();
+    let stats = Stats::new(&record.scores, catalog.as_ref());
+
+    if json {
+        let output = serde_json::json!({
+            "userid": record.scores.userid,
+            "captured_at": record.captured_at,
+            "stats": stats,
+        });
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        return ExitCode::SUCCESS;
+    }
+
+    println!("用户 ID：{}", record.scores.userid);
+    println!(
+        "捕获时间：{}",
+        history::format_timestamp(record.captured_at)
+    );
+
+    println!();
+    println!("难度\t游玩\t通关\t全连\t全良");
+    for (level, crowns) in &stats.by_difficulty {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            songs_score::difficulty_name(*level),
+            crowns.played,
+            crowns.clear,
+            crowns.full_combo,
+            crowns.donderful
+        );
+    }
+
+    println!();
+    println!("星级\t游玩\t通关\t全连\t全良");
+    for (stars, crowns) in stats.by_stars.iter().rev() {
+        println!(
+            "★{}\t{}\t{}\t{}\t{}",
+            stars, crowns.played, crowns.clear, crowns.full_combo, crowns.donderful
+        );
+    }
+
+    println!();
+    let ranks = stats
+        .ranks
+        .iter()
+        .rev()
+        .map(|(rank, count)| format!("{} {}", songs_score::rank_name(*rank), count))
+        .collect::<Vec<_>>();
+    println!("评价分布：{}", ranks.join("，"));
+
+    let accuracy = &stats.accuracy;
+    println!(
+        "良 / 可 / 不可：{} / {} / {}（良率 {:.1}%，可率 {:.1}%，不可率 {:.1}%）",
+        accuracy.good,
+        accuracy.ok,
+        accuracy.ng,
+        accuracy.good_rate * 100.0,
+        accuracy.ok_rate * 100.0,
+        accuracy.ng_rate * 100.0
+    );
+    println!("连打总数：{}", stats.drumroll_hits);
+
+    println!();
+    println!("各分类游玩次数：");
+    let mut genres = stats.plays_by_genre.iter().collect::<Vec<_>>();
+    genres.sort_by(|a, b| b.1.cmp(a.1));
+    for (genre, plays) in genres {
+        println!("  {}：{}", genre, plays);
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// 命令行参数中的公式优先于配置文件，找不到公式时输出错误并返回 `None`
+async fn load_formula(
+    config_dir: &Path,
+    formula: Option<String>,
+) -> Option<&'static dyn RatingFormula> {
+    let config = match Config::load(config_dir).await {
+        Ok(config) => config,
+        Err(err) => {
+            println!("{}", err);
+            return None;
+        }
+    };
+
+    let Some(version) = formula.or(config.rating_formula) else {
+        return Some(rating::default_formula());
+    };
+    let formula = rating::formula(&version);
+    if formula.is_none() {
+        let versions = rating::FORMULAS
+            .iter()
+            .map(|formula| formula.version())
+            .collect::<Vec<_>>();
+        println!(
+            "未知的评分公式 {}，可用的公式：{}",
+            version,
+            versions.join("、")
+        );
+    }
+    formula
+}
+
+pub async fn rating(formula: Option<String>, json: bool) {
+    let config_dir = crate::get_config_dir();
+    let Some(formula) = load_formula(&config_dir, formula).await else {
+        return;
+    };
+
+    let history = History::new(&config_dir);
+    let Some(record) = history.latest().await.expect("无法读取捕获记录") else {
+        println!("还没有捕获记录，请先捕获一次分数数据");
+        return;
+    };
+    let catalog = Catalog::load(&config_dir).await.ok();
+    let report = rating::rate(formula, &record.scores, catalog.as_ref());
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        return;
+    }
+
+    println!("评分公式：{}", report.formula);
+    println!(
+        "综合评分：{:.2}（最好的 {} 个谱面的平均值）",
+        report.rating, report.best_n
+    );
+    println!();
+    println!("排名\t评分\t星级\t难度\t分数\t曲名");
+    for (index, contribution) in report
+        .contributions
+        .iter()
+        .take_while(|x| x.counted)
+        .enumerate()
+    {
+        println!(
+            "{}\t{:.2}\t★{}\t{}\t{}\t{}",
+            index + 1,
+            contribution.rating,
+            contribution.stars.unwrap_or_default(),
+            songs_score::difficulty_name(contribution.level),
+            contribution.high_score,
+            contribution.song_name
+        );
+    }
+}
+
+/// 有文件无法导入或者目标进度无法更新时返回失败的退出码
+pub async fn import(files: Vec<PathBuf>, userid: Option<String>) -> ExitCode {
+    let config_dir = crate::get_config_dir();
+
+    let (mut imported, mut skipped, mut failed) = (0, 0, 0);
+    for path in files {
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(err) => {
+                eprintln!("无法读取 {}：{}", path.to_string_lossy(), err);
+                failed += 1;
+                continue;
+            }
+        };
+        let records = match import::parse(&data, userid.as_deref()) {
+            Ok(records) => records,
+            Err(err) => {
+                eprintln!("无法导入 {}：{}", path.to_string_lossy(), err);
+                failed += 1;
+                continue;
+            }
+        };
+
+        for entry in records {
+            let record = match import::save(&config_dir, entry).await {
+                Ok(Some(record)) => record,
+                Ok(None) => {
+                    skipped += 1;
+                    continue;
+                }
+                Err(err) => {
+                    eprintln!("无法导入 {}：{}", path.to_string_lossy(), err);
+                    failed += 1;
+                    continue;
+                }
+            };
+            println!(
+                "已导入 {}：用户 {}，{}，{} 条成绩",
+                path.to_string_lossy(),
+                record.scores.userid,
+                history::format_timestamp(record.captured_at),
+                record.scores.score_info.len()
+            );
+            imported += 1;
+        }
+    }
+
+    println!(
+        "共导入 {} 条捕获记录，跳过了 {} 条已有的记录",
+        imported, skipped
+    );
+    if imported > 0
+        && let Err(err) = import::update_goals(&config_dir).await
+    {
+        eprintln!("无法更新目标进度：{}", err);
+        return ExitCode::FAILURE;
+    }
+
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// 没有符合条件的捕获记录或者查询条件无效时把原因输出到标准错误，并返回失败的退出码
+pub async fn scores(command: ScoresCommand) -> ExitCode {
+    let ScoresCommand::Query(query) = command;
+    let config_dir = crate::get_config_dir();
+    let records = match History::new(&config_dir).load_all().await {
+        Ok(records) => records,
+        Err(err) => {
+            eprintln!("无法读取捕获记录：{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(record) = query.select(&records) else {
+        eprintln!("没有符合条件的捕获记录，请先捕获一次分数数据");
+        return ExitCode::FAILURE;
+    };
+    let catalog = Catalog::load(&config_dir).await.ok();
+    let items = match query.apply(record, catalog.as_ref()) {
+        Ok(items) => items,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match query.format {
+        OutputFormat::Json => {
+            let mut output = record.clone();
+            output.partial = items.len() < record.scores.score_info.len();
+            output.scores.score_info = items.into_iter().cloned().collect();
+            println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        }
+        OutputFormat::Csv => print!("{}", query::to_csv(record, &items, catalog.as_ref())),
+        OutputFormat::Table => {
+            println!(
+                "捕获时间：{}，共 {} 条成绩",
+                history::format_timestamp(record.captured_at),
+                items.len()
+            );
+            println!();
+            println!("编号\t难度\t星级\t分数\t评价\t王冠\t良/可/不可\t游玩\t最近游玩\t曲名");
+            for item in items {
+                let detail = Catalog::detail_for(catalog.as_ref(), item);
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}/{}/{}\t{}\t{}\t{}",
+                    item.song_no,
+                    songs_score::difficulty_name(item.level),
+                    detail
+                        .stars(item.level)
+                        .map(|x| format!("★{}", x))
+                        .unwrap_or_default(),
+                    item.high_score,
+                    songs_score::rank_name(item.best_score_rank),
+                    item.crown_name(),
+                    item.good_cnt,
+                    item.ok_cnt,
+                    item.ng_cnt,
+                    item.stage_cnt,
+                    item.update_datetime,
+                    detail.song_name
+                );
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// 配置、目标进度无法读取或者目标无法修改时把原因输出到标准错误，并返回失败的退出码
+pub async fn goals(command: GoalsCommand) -> ExitCode {
+    let config_dir = crate::get_config_dir();
+    let config = match Config::load(&config_dir).await {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut state = match GoalState::load(&config_dir).await {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("无法读取目标进度：{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match command {
+        GoalsCommand::List => {
+            let goals = state.all_goals(&config.goals);
+            if goals.is_empty() {
+                println!("还没有设定目标，可以在配置文件中添加，或者使用 goals add 命令添加");
+                return ExitCode::SUCCESS;
+            }
+
+            let record = match History::new(&config_dir).latest().await {
+                Ok(record) => record,
+                Err(err) => {
+                    eprintln!("无法读取捕获记录：{}", err);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let catalog = Catalog::load(&config_dir).await.ok();
+            for goal in goals {
+                let met_at = state.progress.get(&goal.name).and_then(|x| x.met_at);
+                let status = match (&record, met_at) {
+                    (_, Some(met_at)) => {
+                        format!("已于 {} 达成", history::format_timestamp(met_at))
+                    }
+                    (Some(record), None) => {
+                        let (achieved, required) = goal.progress(&record.scores, catalog.as_ref());
+                        format!("{}/{}", achieved, required)
+                    }
+                    (None, None) => "还没有捕获记录".to_string(),
+                };
+                println!("{}：{}（{}）", goal.name, goal.describe(), status);
+            }
+        }
+        GoalsCommand::Add { goal } => {
+            if let Err(err) = goal.validate() {
+                eprintln!("{}", err);
+                return ExitCode::FAILURE;
+            }
+            if state
+                .all_goals(&config.goals)
+                .iter()
+                .any(|x| x.name == goal.name)
+            {
+                eprintln!("已经有名称为「{}」的目标", goal.name);
+                return ExitCode::FAILURE;
+            }
+
+            let description = format!("已添加目标「{}」：{}", goal.name, goal.describe());
+            state.goals.push(goal);
+            if let Err(err) = state.save(&config_dir).await {
+                eprintln!("无法保存目标：{}", err);
+                return ExitCode::FAILURE;
+            }
+            println!("{}", description);
+        }
+        GoalsCommand::Remove { name } => {
+            let Some(index) = state.goals.iter().position(|x| x.name == name) else {
+                if config.goals.iter().any(|x| x.name == name) {
+                    eprintln!("目标「{}」写在配置文件中，请在配置文件中删除", name);
+                } else {
+                    eprintln!("没有名称为「{}」的目标", name);
+                }
+                return ExitCode::FAILURE;
+            };
+
+            state.goals.remove(index);
+            state.progress.remove(&name);
+            if let Err(err) = state.save(&config_dir).await {
+                eprintln!("无法保存目标：{}", err);
+                return ExitCode::FAILURE;
+            }
+            println!("已删除目标「{}」", name);
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+pub async fn targets(limit: usize, json: bool) {
+    let config_dir = crate::get_config_dir();
+    let history = History::new(&config_dir);
+    let Some(record) = history.latest().await.expect("无法读取捕获记录") else {
+        println!("还没有捕获记录，请先捕获一次分数数据");
+        return;
+    };
+    let catalog = Catalog::load(&config_dir).await.ok();
+    let mut targets = targets::next_targets(&record.scores, catalog.as_ref());
+    targets.truncate(limit);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&targets).unwrap());
+        return;
+    }
+
+    if let Some(stars) = targets::player_stars(&record.scores, catalog.as_ref()) {
+        println!("玩家星级：★{}（全连目标只包括不超过这个星级的谱面）", stars);
+    }
+    if targets.is_empty() {
+        println!("暂时没有接近下一个评价或王冠的谱面");
+        return;
+    }
+    for (index, target) in targets.iter().enumerate() {
+        println!("{}. {}", index + 1, target);
+    }
+}
+
+pub async fn card(output: PathBuf, formula: Option<String>) {
+    let Some(format) = CardFormat::from_path(&output) else {
+        println!("无法识别的图片格式，输出文件的扩展名需要是 .png 或 .svg");
+        return;
+    };
+
+    let config_dir = crate::get_config_dir();
+    let Some(formula) = load_formula(&config_dir, formula).await else {
+        return;
+    };
+
+    let history = History::new(&config_dir);
+    let Some(record) = history.latest().await.expect("无法读取捕获记录") else {
+        println!("还没有捕获记录，请先捕获一次分数数据");
+        return;
+    };
+    let catalog = Catalog::load(&config_dir).await.ok();
+    let report = rating::rate(formula, &record.scores, catalog.as_ref());
+
+    let data = Card::new(&record, &report)
+        .encode(format)
+        .expect("无法生成成绩卡片");
+    match tokio::fs::write(&output, data).await {
+        Ok(()) => println!("成绩卡片已保存到 {}", output.to_string_lossy()),
+        Err(err) => println!("无法保存成绩卡片：{}", err),
+    }
+}
+
+pub async fn report(output: PathBuf, formula: Option<String>) {
+    let config_dir = crate::get_config_dir();
+    let Some(formula) = load_formula(&config_dir, formula).await else {
+        return;
+    };
+
+    let records = History::new(&config_dir)
+        .load_all()
+        .await
+        .expect("无法读取捕获记录");
+    if records.is_empty() {
+        println!("还没有捕获记录，请先捕获一次分数数据");
+        return;
+    }
+    let catalog = Catalog::load(&config_dir).await.ok();
+
+    let html = report::render(&records, catalog.as_ref(), formula);
+    match tokio::fs::write(&output, html).await {
+        Ok(()) => println!("进度报告已保存到 {}", output.to_string_lossy()),
+        Err(err) => println!("无法保存进度报告：{}", err),
+    }
+}
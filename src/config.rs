@@ -0,0 +1,83 @@
+//! 配置目录中的 `config.json`，文件不存在时使用默认配置
+
+use std::{fmt, io, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    goals::Goal,
+    rules::{self, CaptureRule, RelayTarget, RuleSet},
+};
+
+pub const CONFIG_FILE_NAME: &str = "config.json";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// 抓取规则，填写后会完全代替内置的规则
+    pub rules: Vec<CaptureRule>,
+    /// 接收分数的网站，填写后代替规则中原有的同步接口，可以同时填写多个
+    pub relay_targets: Vec<RelayTarget>,
+    /// 评分公式的版本，不填时使用默认公式
+    pub rating_formula: Option<String>,
+    /// 玩家设定的目标，通过命令添加的目标保存在 `goals.json` 中
+    pub goals: Vec<Goal>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rules: rules::default_rules(),
+            relay_targets: Vec::new(),
+            rating_formula: None,
+            goals: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "无法读取配置文件：{err}"),
+            Self::Parse(err) => write!(f, "配置文件格式错误：{err}"),
+            Self::Invalid(message) => write!(f, "配置文件无效：{message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub async fn load(config_dir: &Path) -> Result<Self, ConfigError> {
+        let path = config_dir.join(CONFIG_FILE_NAME);
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(ConfigError::Io(err)),
+        };
+
+        tracing::info!("正在使用配置文件 {}", path.to_string_lossy());
+        let config: Self = serde_json::from_slice(&data).map_err(ConfigError::Parse)?;
+        for goal in &config.goals {
+            goal.validate().map_err(ConfigError::Invalid)?;
+        }
+        Ok(config)
+    }
+}
+
+/// 读取配置文件中的抓取规则
+pub async fn load_rules(config_dir: &Path) -> Result<RuleSet, ConfigError> {
+    let mut config = Config::load(config_dir).await?;
+    if !config.relay_targets.is_empty() {
+        rules::replace_relay_targets(&mut config.rules, config.relay_targets)
+            .map_err(ConfigError::Invalid)?;
+    }
+    RuleSet::new(config.rules).map_err(ConfigError::Invalid)
+}
@@ -0,0 +1,317 @@
+//! 玩家设定的目标，每次捕获到分数后重新计算进度
+//!
+//! 目标可以写在配置文件中，也可以通过 `goals add` 命令添加。命令添加的目标和所有目标的进度
+//! 保存在配置目录的 `goals.json` 中，和捕获记录放在一起
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    path::Path,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    catalog::Catalog,
+    songs_score::{self, ScoreInfo, SongDetail},
+};
+
+pub const GOALS_FILE_NAME: &str = "goals.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Crown {
+    Clear,
+    FullCombo,
+    Donderful,
+}
+
+impl Crown {
+    pub fn reached(self, item: &ScoreInfo) -> bool {
+        match self {
+            Self::Clear => {
+                item.clear_cnt > 0 || item.full_combo_cnt > 0 || item.dondaful_combo_cnt > 0
+            }
+            Self::FullCombo => item.full_combo_cnt > 0 || item.dondaful_combo_cnt > 0,
+            Self::Donderful => item.dondaful_combo_cnt > 0,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Clear => "通关",
+            Self::FullCombo => "全连",
+            Self::Donderful => "全良",
+        }
+    }
+}
+
+/// 评价，可以写编号（2 到 8）或者名称，例如 `"金雅"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "serde_json::Value", into = "i64")]
+pub struct Rank(i64);
+
+impl FromStr for Rank {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let rank = match s.parse::<i64>() {
+            Ok(rank) => rank,
+            Err(_) => (2..=8)
+                .find(|rank| songs_score::rank_name(*rank) == s)
+                .ok_or_else(|| format!("未知的评价 {s}"))?,
+        };
+        if !(2..=8).contains(&rank) {
+            return Err(format!("评价编号需要在 2 到 8 之间：{rank}"));
+        }
+        Ok(Self(rank))
+    }
+}
+
+impl TryFrom<serde_json::Value> for Rank {
+    type Error = String;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        match value {
+            serde_json::Value::String(s) => s.parse(),
+            value => value.to_string().parse(),
+        }
+    }
+}
+
+impl Rank {
+    pub fn reached(self, item: &ScoreInfo) -> bool {
+        item.best_score_rank >= self.0
+    }
+}
+
+impl From<Rank> for i64 {
+    fn from(rank: Rank) -> Self {
+        rank.0
+    }
+}
+
+/// 一个目标，歌曲满足所有条件中的谱面达到指定的王冠和评价后计为完成一首
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, clap::Args)]
+pub struct Goal {
+    /// 目标名称，同时用于记录进度，不能重复
+    #[arg(long)]
+    pub name: String,
+    /// 需要达到的王冠
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crown: Option<Crown>,
+    /// 需要达到的评价，可以是编号（2 到 8）或者名称，例如 金雅
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<Rank>,
+    /// 只包括指定编号的歌曲
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song_no: Option<i64>,
+    /// 只包括指定难度（1 为简单，5 为里魔王）的谱面
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<i64>,
+    /// 只包括指定星级的谱面
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stars: Option<u8>,
+    /// 只包括指定分类的歌曲
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family: Option<String>,
+    /// 需要完成的歌曲数量，不填时需要完成所有符合条件的歌曲
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+}
+
+impl Goal {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("目标名称不能为空".to_string());
+        }
+        if self.crown.is_none() && self.rank.is_none() {
+            return Err(format!("目标「{}」需要指定王冠或者评价", self.name));
+        }
+        if let Some(level) = self.level
+            && !(1..=5).contains(&level)
+        {
+            return Err(format!("目标「{}」的难度需要在 1 到 5 之间", self.name));
+        }
+        Ok(())
+    }
+
+    fn matches_song(&self, song_no: i64, family: &str) -> bool {
+        self.song_no.is_none_or(|x| x == song_no)
+            && self.family.as_deref().is_none_or(|x| x == family)
+    }
+
+    fn matches_chart(&self, item: &ScoreInfo, catalog: Option<&Catalog>) -> bool {
+        let detail = Catalog::detail_for(catalog, item);
+        self.matches_song(item.song_no, &detail.family)
+            && self.level.is_none_or(|x| x == item.level)
+            && self
+                .stars
+                .is_none_or(|x| detail.stars(item.level) == Some(x))
+    }
+
+    /// 歌曲目录中的歌曲是否有符合条件的谱面
+    fn matches_detail(&self, song_no: i64, detail: &SongDetail) -> bool {
+        let levels = match self.level {
+            Some(level) => level..=level,
+            None => 1..=5,
+        };
+        self.matches_song(song_no, &detail.family)
+            && levels
+                .filter_map(|level| detail.stars(level))
+                .any(|stars| self.stars.is_none_or(|x| x == stars))
+    }
+
+    fn reached(&self, item: &ScoreInfo) -> bool {
+        self.crown.is_none_or(|crown| crown.reached(item))
+            && self.rank.is_none_or(|rank| rank.reached(item))
+    }
+
+    /// 已完成和需要完成的歌曲数量，符合条件的歌曲包括歌曲目录中还没有玩过的歌曲
+    pub fn progress(&self, data: &songs_score::Data, catalog: Option<&Catalog>) -> (usize, usize) {
+        let mut songs = BTreeSet::new();
+        let mut achieved = BTreeSet::new();
+        for item in data
+            .score_info
+            .iter()
+            .filter(|item| self.matches_chart(item, catalog))
+        {
+            songs.insert(item.song_no);
+            if self.reached(item) {
+                achieved.insert(item.song_no);
+            }
+        }
+        if let Some(catalog) = catalog {
+            songs.extend(
+                catalog
+                    .songs()
+                    .filter(|(song_no, song)| self.matches_detail(*song_no, &song.detail))
+                    .map(|(song_no, _)| song_no),
+            );
+        }
+
+        (achieved.len(), self.count.unwrap_or(songs.len()))
+    }
+
+    /// 说明目标的条件，例如「魔王 ★9 全连 20 首」
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(family) = &self.family {
+            parts.push(family.clone());
+        }
+        if let Some(song_no) = self.song_no {
+            parts.push(format!("歌曲 {song_no}"));
+        }
+        if let Some(level) = self.level {
+            parts.push(songs_score::difficulty_name(level).to_string());
+        }
+        if let Some(stars) = self.stars {
+            parts.push(format!("★{stars}"));
+        }
+        if let Some(crown) = self.crown {
+            parts.push(crown.name().to_string());
+        }
+        if let Some(rank) = self.rank {
+            parts.push(format!("{}以上", songs_score::rank_name(rank.0)));
+        }
+        match (self.count, self.song_no) {
+            (Some(count), _) => parts.push(format!("{count} 首")),
+            (None, None) => parts.push("所有歌曲".to_string()),
+            (None, Some(_)) => {}
+        }
+        parts.join(" ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GoalProgress {
+    pub achieved: usize,
+    pub required: usize,
+    /// 最后一次计算进度时的捕获时间
+    pub updated_at: i64,
+    /// 第一次达成目标时的捕获时间
+    #[serde(default)]
+    pub met_at: Option<i64>,
+}
+
+impl GoalProgress {
+    pub fn is_met(&self) -> bool {
+        self.required > 0 && self.achieved >= self.required
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GoalState {
+    /// 通过命令添加的目标
+    pub goals: Vec<Goal>,
+    /// 以目标名称为键的进度
+    pub progress: BTreeMap<String, GoalProgress>,
+}
+
+impl GoalState {
+    /// 读取目标状态，文件不存在时返回空的状态
+    pub async fn load(config_dir: &Path) -> io::Result<Self> {
+        match tokio::fs::read(config_dir.join(GOALS_FILE_NAME)).await {
+            Ok(data) => serde_json::from_slice(&data).map_err(io::Error::other),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn save(&self, config_dir: &Path) -> io::Result<()> {
+        tokio::fs::create_dir_all(config_dir).await?;
+        let data = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        tokio::fs::write(config_dir.join(GOALS_FILE_NAME), data).await
+    }
+
+    /// 配置文件中的目标在前，名称重复时只保留第一个
+    pub fn all_goals<'a>(&'a self, config_goals: &'a [Goal]) -> Vec<&'a Goal> {
+        let mut names = BTreeSet::new();
+        config_goals
+            .iter()
+            .chain(&self.goals)
+            .filter(|goal| names.insert(goal.name.as_str()))
+            .collect()
+    }
+
+    /// 用一次捕获重新计算所有目标的进度，已经删除的目标的进度会被清除，返回这次新达成的目标
+    pub fn update(
+        &mut self,
+        config_goals: &[Goal],
+        data: &songs_score::Data,
+        catalog: Option<&Catalog>,
+        captured_at: i64,
+    ) -> Vec<Goal> {
+        let mut progress = BTreeMap::new();
+        let mut newly_met = Vec::new();
+
+        for goal in self.all_goals(config_goals) {
+            let (achieved, required) = goal.progress(data, catalog);
+            let previous = self.progress.get(&goal.name);
+            let mut current = GoalProgress {
+                achieved,
+                required,
+                updated_at: captured_at,
+                met_at: previous.and_then(|x| x.met_at),
+            };
+            if current.met_at.is_none() && current.is_met() {
+                current.met_at = Some(captured_at);
+                newly_met.push(goal.clone());
+            }
+            progress.insert(goal.name.clone(), current);
+        }
+
+        self.progress = progress;
+        newly_met
+    }
+}
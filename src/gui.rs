@@ -21,12 +21,24 @@ mod noop;
 use noop::*;
 
 #[derive(Debug, Clone)]
+// 没有图形界面的平台不会读取消息内容
+#[cfg_attr(not(any(target_os = "windows", target_os = "macos")), allow(dead_code))]
 pub enum GuiMessage {
     Init,
+    // 代理服务器无法启动，例如私钥口令错误
+    InitError(String),
     // 需要信任证书（仅 macOS）
     CertTrustNeeded,
     WaitForScoreData,
     WaitForScoreSync,
+    // 捕获到的分数的统计摘要
+    ScoreStats(String),
+    // 离下一个评价或王冠最近的几个谱面
+    NextTargets(Vec<String>),
+    // 这次捕获新达成的目标名称
+    GoalsMet(Vec<String>),
+    // 分数数据处理失败，响应仍然会原样转发给小程序
+    CaptureFailed(String),
     SendingScoreData,
     Close,
 }
@@ -1,8 +1,8 @@
 use cacao::{
     appkit::{
+        App, AppDelegate,
         menu::{Menu, MenuItem},
         window::{Window, WindowConfig, WindowDelegate, WindowStyle},
-        App, AppDelegate,
     },
     button::Button,
     color::Color,
@@ -59,6 +59,8 @@ pub struct MainWindowDelegate {
     label_sync_score: Label,
 
     label_description: Label,
+    label_targets: Label,
+    label_goals: Label,
     button_trust_guide: Button,
 }
 
@@ -75,6 +77,8 @@ impl MainWindowDelegate {
             label_sync_score: Default::default(),
 
             label_description: Default::default(),
+            label_targets: Default::default(),
+            label_goals: Default::default(),
             button_trust_guide: Button::new("证书信任指南"),
         }
     }
@@ -106,6 +110,8 @@ impl WindowDelegate for MainWindowDelegate {
         self.content.add_subview(&self.label_receive_score);
         self.content.add_subview(&self.label_sync_score);
         self.content.add_subview(&self.label_description);
+        self.content.add_subview(&self.label_targets);
+        self.content.add_subview(&self.label_goals);
 
         self.button_trust_guide.set_action(|| {
             // open url
@@ -189,7 +195,33 @@ impl WindowDelegate for MainWindowDelegate {
                 .trailing
                 .constraint_equal_to(&self.content.trailing)
                 .offset(-10.0),
-            self.label_description
+            //
+            self.label_targets
+                .top
+                .constraint_equal_to(&self.label_description.bottom)
+                .offset(10.0),
+            self.label_targets
+                .leading
+                .constraint_equal_to(&self.content.leading)
+                .offset(10.0),
+            self.label_targets
+                .trailing
+                .constraint_equal_to(&self.content.trailing)
+                .offset(-10.0),
+            //
+            self.label_goals
+                .top
+                .constraint_equal_to(&self.label_targets.bottom)
+                .offset(10.0),
+            self.label_goals
+                .leading
+                .constraint_equal_to(&self.content.leading)
+                .offset(10.0),
+            self.label_goals
+                .trailing
+                .constraint_equal_to(&self.content.trailing)
+                .offset(-10.0),
+            self.label_goals
                 .bottom
                 .constraint_equal_to(&self.content.bottom)
                 .offset(-10.0),
@@ -229,6 +261,13 @@ impl Dispatcher for MainApp {
     fn on_ui_message(&self, msg: Self::Message) {
         let delegate = self.window.delegate.as_ref().unwrap();
         match msg {
+            GuiMessage::InitError(msg) => {
+                delegate.label_launch_proxy.set_text_color(Color::SystemRed);
+
+                delegate.label_description.set_text(format!(
+                    "代理服务器无法启动：{msg}。请修改后重新打开本程序。"
+                ));
+            }
             GuiMessage::CertTrustNeeded => {
                 delegate
                     .label_launch_proxy
@@ -264,6 +303,30 @@ impl Dispatcher for MainApp {
 
                 delegate.label_description.set_text("最后，请打开 Don Note 小程序，切换到 数据同步 页面，点击 成绩同步 按钮，即可完成数据同步啦！");
             }
+            GuiMessage::ScoreStats(summary) => {
+                delegate
+                    .label_receive_score
+                    .set_text(format!("3. 已接收分数数据（{summary}）"));
+            }
+            GuiMessage::NextTargets(targets) if !targets.is_empty() => {
+                delegate
+                    .label_targets
+                    .set_text(format!("下次练习目标：\n{}", targets.join("\n")));
+            }
+            GuiMessage::GoalsMet(names) => {
+                delegate
+                    .label_goals
+                    .set_text(format!("恭喜达成目标：{}", names.join("、")));
+            }
+            GuiMessage::CaptureFailed(msg) => {
+                delegate
+                    .label_receive_score
+                    .set_text_color(Color::SystemRed);
+
+                delegate.label_description.set_text(format!(
+                    "{msg}。请重新打开 鼓众广场 小程序的 游戏成绩 页面重试。"
+                ));
+            }
             GuiMessage::SendingScoreData => {
                 delegate.label_sync_score.set_text_color(Color::SystemGreen);
 
@@ -4,8 +4,8 @@ use tokio::{
 };
 
 pub fn gui_main(
-    handle: &Handle,
-    close_sx: Sender<()>,
-    mut gui_rx: UnboundedReceiver<super::GuiMessage>,
+    _handle: &Handle,
+    _close_sx: Sender<()>,
+    _gui_rx: UnboundedReceiver<super::GuiMessage>,
 ) {
 }
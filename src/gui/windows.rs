@@ -5,15 +5,15 @@ use tokio::{
     sync::mpsc::{Sender, UnboundedReceiver},
 };
 use winsafe::{
+    AnyResult, COLORREF, HBRUSH,
     co::{BKMODE, WS_EX},
     gui::*,
     prelude::*,
-    AnyResult, COLORREF, HBRUSH,
 };
 
 const LABEL_PROGRESS_COLOR: COLORREF = COLORREF::new(0x24, 0x95, 0xFF);
 const LABEL_SUCCESS_COLOR: COLORREF = COLORREF::new(0x38, 0xD0, 0x6B);
-// const LABEL_ERROR_COLOR: COLORREF = COLORREF::new(0xFF, 0x00, 0x00);
+const LABEL_ERROR_COLOR: COLORREF = COLORREF::new(0xFF, 0x00, 0x00);
 const LABEL_DEFAULT_COLOR: COLORREF = COLORREF::new(0x00, 0x00, 0x00);
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -22,7 +22,7 @@ enum LabelColor {
     Default,
     Progress,
     Success,
-    // Error,
+    Error,
 }
 
 impl LabelColor {
@@ -30,7 +30,7 @@ impl LabelColor {
         match self {
             LabelColor::Progress => LABEL_PROGRESS_COLOR,
             LabelColor::Success => LABEL_SUCCESS_COLOR,
-            // LabelColor::Error => LABEL_ERROR_COLOR,
+            LabelColor::Error => LABEL_ERROR_COLOR,
             _ => LABEL_DEFAULT_COLOR,
         }
     }
@@ -47,7 +47,7 @@ pub fn gui_main(
     let win = WindowMain::new(WindowMainOpts {
         class_name: "TaikoScoreGetter".to_string(),
         title: "Taiko Score Getter 太鼓成绩获取工具".to_string(),
-        size: (560, 115),
+        size: (560, 115 + 20 * 8),
         class_icon: Icon::Id(1),
         class_bg_brush: Brush::Handle(
             HBRUSH::CreateSolidBrush(COLORREF::new(0xFF, 0xFF, 0xFF))
@@ -101,6 +101,26 @@ pub fn gui_main(
         },
     );
 
+    let label_targets = Label::new(
+        &win,
+        LabelOpts {
+            text: String::new(),
+            position: (10, 10 + 20 * 7),
+            size: (560 - 10 - 10, 20 * 4),
+            ..Default::default()
+        },
+    );
+
+    let label_goals = Label::new(
+        &win,
+        LabelOpts {
+            text: String::new(),
+            position: (10, 10 + 20 * 11),
+            size: (560 - 10 - 10, 20 * 2),
+            ..Default::default()
+        },
+    );
+
     win.on().wm_close({
         let win = win.clone();
         let close_sx = close_sx.clone();
@@ -168,6 +188,8 @@ pub fn gui_main(
         let label_sync_score = label_sync_score.clone();
         let label_receive_score = label_receive_score.clone();
         let label_description = label_description.clone();
+        let label_targets = label_targets.clone();
+        let label_goals = label_goals.clone();
 
         let label_launch_proxy_color = label_launch_proxy_color.clone();
         let label_receive_score_color = label_receive_score_color.clone();
@@ -203,9 +225,28 @@ pub fn gui_main(
                     super::GuiMessage::CertTrustNeeded => {
                         // 一般不会出现需要验证证书信任的这个情况
                     }
-                    // super::GuiMessage::InitError(_msg) => {
-                    //     // TODO: 显示点什么？
-                    // }
+                    super::GuiMessage::InitError(msg) => {
+                        *label_launch_proxy_color.lock().unwrap() = LabelColor::Error;
+
+                        win.run_ui_thread({
+                            let win = win.clone();
+
+                            let label_launch_proxy = label_launch_proxy.clone();
+                            let label_description = label_description.clone();
+
+                            move || {
+                                label_description.set_text_and_resize(&format!(
+                                    "代理服务器无法启动：{msg}。请修改后重新打开本程序。"
+                                ));
+
+                                win.hwnd().InvalidateRect(None, true)?;
+                                label_launch_proxy.hwnd().InvalidateRect(None, true)?;
+                                label_description.hwnd().InvalidateRect(None, true)?;
+
+                                Ok(())
+                            }
+                        });
+                    }
                     super::GuiMessage::WaitForScoreData => {
                         *label_launch_proxy_color.lock().unwrap() = LabelColor::Success;
                         *label_receive_score_color.lock().unwrap() = LabelColor::Progress;
@@ -256,6 +297,80 @@ pub fn gui_main(
                             }
                         });
                     }
+                    super::GuiMessage::ScoreStats(summary) => {
+                        win.run_ui_thread({
+                            let label_receive_score = label_receive_score.clone();
+
+                            move || {
+                                label_receive_score.set_text_and_resize(&format!(
+                                    "2. 已接收分数数据（{summary}）"
+                                ));
+                                label_receive_score.hwnd().InvalidateRect(None, true)?;
+
+                                Ok(())
+                            }
+                        });
+                    }
+                    super::GuiMessage::NextTargets(targets) => {
+                        if targets.is_empty() {
+                            continue;
+                        }
+
+                        win.run_ui_thread({
+                            let label_targets = label_targets.clone();
+
+                            move || {
+                                label_targets.set_text_and_resize(&format!(
+                                    "下次练习目标：\n{}",
+                                    targets.join("\n")
+                                ));
+                                label_targets.hwnd().InvalidateRect(None, true)?;
+
+                                Ok(())
+                            }
+                        });
+                    }
+                    super::GuiMessage::GoalsMet(names) => {
+                        win.run_ui_thread({
+                            let label_goals = label_goals.clone();
+
+                            move || {
+                                label_goals.set_text_and_resize(&format!(
+                                    "恭喜达成目标：{}",
+                                    names.join("、")
+                                ));
+                                label_goals.hwnd().InvalidateRect(None, true)?;
+
+                                Ok(())
+                            }
+                        });
+                    }
+                    super::GuiMessage::CaptureFailed(msg) => {
+                        *label_receive_score_color.lock().unwrap() = LabelColor::Error;
+
+                        win.run_ui_thread({
+                            let win = win.clone();
+
+                            let label_launch_proxy = label_launch_proxy.clone();
+                            let label_sync_score = label_sync_score.clone();
+                            let label_receive_score = label_receive_score.clone();
+                            let label_description = label_description.clone();
+
+                            move || {
+                                label_description.set_text_and_resize(&format!(
+                                    "{msg}。请重新打开 鼓众广场 小程序的 游戏成绩 页面重试。"
+                                ));
+
+                                win.hwnd().InvalidateRect(None, true)?;
+                                label_launch_proxy.hwnd().InvalidateRect(None, true)?;
+                                label_sync_score.hwnd().InvalidateRect(None, true)?;
+                                label_receive_score.hwnd().InvalidateRect(None, true)?;
+                                label_description.hwnd().InvalidateRect(None, true)?;
+
+                                Ok(())
+                            }
+                        });
+                    }
                     super::GuiMessage::SendingScoreData => {
                         *label_sync_score_color.lock().unwrap() = LabelColor::Success;
 
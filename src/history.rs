@@ -0,0 +1,151 @@
+//! 捕获记录，每次捕获到分数后在配置目录的 `history` 目录中保存一个文件
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::songs_score;
+
+pub const HISTORY_DIR_NAME: &str = "history";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// 捕获时间，Unix 时间戳（秒）
+    pub captured_at: i64,
+    pub scores: songs_score::Data,
+    /// 只包括部分成绩，`scores query` 筛选后导出时会带有这个标记，导入时需要合并到更早的捕获记录中
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+}
+
+impl CaptureRecord {
+    fn file_name(&self) -> String {
+        // 用户 ID 来自服务器的响应，只保留可以安全用于文件名的字符
+        let userid = self
+            .scores
+            .userid
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>();
+        format!("{}-{}.json", userid, self.captured_at)
+    }
+}
+
+/// 规则转换时解析出的数据，会保存到捕获记录中
+#[derive(Debug, Clone)]
+pub enum CapturedPart {
+    Scores(songs_score::Data),
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    pub fn new(config_dir: &Path) -> Self {
+        Self {
+            dir: config_dir.join(HISTORY_DIR_NAME),
+        }
+    }
+
+    /// 保存捕获记录，同一用户同一时间的记录会被覆盖
+    pub async fn save(&self, record: &CaptureRecord) -> io::Result<PathBuf> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.dir.join(record.file_name());
+        let data = serde_json::to_vec_pretty(record).map_err(io::Error::other)?;
+        tokio::fs::write(&path, data).await?;
+        Ok(path)
+    }
+
+    /// 保存捕获记录，同一用户同一时间的记录已经存在时不保存并返回 `None`
+    pub async fn save_new(&self, record: &CaptureRecord) -> io::Result<Option<PathBuf>> {
+        if tokio::fs::try_exists(self.dir.join(record.file_name())).await? {
+            return Ok(None);
+        }
+        self.save(record).await.map(Some)
+    }
+
+    /// 按捕获时间从早到晚返回所有记录，无法解析的文件会被跳过
+    pub async fn load_all(&self) -> io::Result<Vec<CaptureRecord>> {
+        let mut records = Vec::new();
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(records),
+            Err(err) => return Err(err),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|x| x != "json") {
+                continue;
+            }
+
+            let data = tokio::fs::read(&path).await?;
+            match serde_json::from_slice::<CaptureRecord>(&data) {
+                Ok(record) => records.push(record),
+                Err(err) => {
+                    tracing::warn!("跳过无法解析的捕获记录 {}：{}", path.to_string_lossy(), err);
+                }
+            }
+        }
+
+        records.sort_by_key(|record| record.captured_at);
+        Ok(records)
+    }
+
+    /// 最近一次的捕获记录
+    pub async fn latest(&self) -> io::Result<Option<CaptureRecord>> {
+        Ok(self.load_all().await?.pop())
+    }
+}
+
+fn beijing_offset() -> time::UtcOffset {
+    time::UtcOffset::from_hms(8, 0, 0).unwrap()
+}
+
+/// 按北京时间格式化捕获时间，与服务器返回的时间一致
+pub fn format_timestamp(timestamp: i64) -> String {
+    let Ok(time) = time::OffsetDateTime::from_unix_timestamp(timestamp) else {
+        return timestamp.to_string();
+    };
+    let time = time.to_offset(beijing_offset());
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+/// 解析服务器返回的 `YYYY-MM-DD HH:MM:SS` 格式的北京时间
+pub fn parse_timestamp(s: &str) -> Option<i64> {
+    let (date, clock) = s.trim().split_once(' ')?;
+    let mut date = date.split('-').map(str::parse::<i64>);
+    let mut clock = clock.split(':').map(str::parse::<u8>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let (hour, minute, second) = (
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+    );
+
+    let date = time::Date::from_calendar_date(
+        i32::try_from(year).ok()?,
+        time::Month::try_from(u8::try_from(month).ok()?).ok()?,
+        u8::try_from(day).ok()?,
+    )
+    .ok()?;
+    let clock = time::Time::from_hms(hour, minute, second).ok()?;
+    Some(
+        time::PrimitiveDateTime::new(date, clock)
+            .assume_offset(beijing_offset())
+            .unix_timestamp(),
+    )
+}
@@ -0,0 +1,353 @@
+//! 从文件导入捕获记录，用于补充以前保存的分数数据
+//!
+//! 支持的格式：
+//! - 鼓众广场分数接口返回的原始 JSON（`songs_score::Response`）
+//! - 同步接口使用的 14 列数组，需要另外指定用户 ID
+//! - `scores query` 导出的 JSON 和 CSV，以及 `history` 目录中的捕获记录
+//!
+//! 没有捕获时间的数据使用最近一次游玩的时间，重复导入同一个文件时得到的记录相同。
+//! 筛选后导出的文件只包括部分成绩，会合并到同一用户更早的捕获记录中
+
+use std::{collections::BTreeMap, fmt, io, path::Path};
+
+use serde_json::Value;
+
+use crate::{
+    catalog::Catalog,
+    history::{self, CaptureRecord, History},
+    songs_score::{self, ScoreInfo},
+};
+
+/// 从文件中解析出的一条捕获记录
+#[derive(Debug, Clone)]
+pub struct Imported {
+    pub record: CaptureRecord,
+    /// 歌曲信息是否和服务器返回的一样完整，CSV 中只有曲名、分类和星级，不会合并到歌曲目录中
+    pub full_details: bool,
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Json(serde_json::Error),
+    Io(io::Error),
+    /// 文件内容可以解析，但是不是支持的格式
+    UnknownFormat,
+    MissingUserid,
+    /// 筛选后导出的部分成绩没有可以合并的捕获记录
+    MissingBaseRecord,
+    Invalid(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json(err) => write!(f, "JSON 格式错误：{err}"),
+            Self::Io(err) => write!(f, "无法读写捕获记录：{err}"),
+            Self::UnknownFormat => write!(f, "无法识别的文件格式"),
+            Self::MissingUserid => write!(f, "文件中没有用户 ID，请使用 --userid 指定"),
+            Self::MissingBaseRecord => write!(
+                f,
+                "文件是筛选后导出的部分成绩，需要先导入同一用户在这之前的完整捕获记录"
+            ),
+            Self::Invalid(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// 解析一个文件，`userid` 用于文件中没有用户 ID 的情况
+pub fn parse(data: &[u8], userid: Option<&str>) -> Result<Vec<Imported>, ImportError> {
+    let text = String::from_utf8_lossy(data);
+    let text = text.trim_start_matches('\u{feff}').trim();
+    if text.starts_with('{') || text.starts_with('[') {
+        let value = serde_json::from_str(text).map_err(ImportError::Json)?;
+        parse_json(value, userid)
+    } else {
+        parse_csv(text, userid)
+    }
+}
+
+fn parse_json(value: Value, userid: Option<&str>) -> Result<Vec<Imported>, ImportError> {
+    match value {
+        Value::Array(rows) => {
+            let score_info = rows
+                .iter()
+                .map(|row| {
+                    ScoreInfo::from_relay_row(row)
+                        .ok_or_else(|| ImportError::Invalid(format!("无法识别的成绩行 {row}")))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(vec![Imported {
+                record: new_record("", None, false, score_info, userid)?,
+                full_details: false,
+            }])
+        }
+        Value::Object(ref object) if object.contains_key("captured_at") => {
+            let record = serde_json::from_value(value).map_err(ImportError::Json)?;
+            Ok(vec![Imported {
+                record,
+                full_details: true,
+            }])
+        }
+        Value::Object(ref object) if object.contains_key("data") => {
+            let response: songs_score::Response =
+                serde_json::from_value(value).map_err(ImportError::Json)?;
+            if response.status != 0 {
+                return Err(ImportError::Invalid(format!(
+                    "服务器返回了错误：{}（{}）",
+                    response.message, response.status
+                )));
+            }
+            let data = response.data;
+            Ok(vec![Imported {
+                record: new_record(&data.userid, None, false, data.score_info, userid)?,
+                full_details: true,
+            }])
+        }
+        _ => Err(ImportError::UnknownFormat),
+    }
+}
+
+/// 文件中的用户 ID 优先，没有捕获时间时使用最近一次游玩的时间
+fn new_record(
+    file_userid: &str,
+    captured_at: Option<i64>,
+    partial: bool,
+    score_info: Vec<ScoreInfo>,
+    userid: Option<&str>,
+) -> Result<CaptureRecord, ImportError> {
+    let userid = match file_userid {
+        "" => userid.ok_or(ImportError::MissingUserid)?,
+        file_userid => file_userid,
+    };
+    let captured_at = match captured_at {
+        Some(captured_at) => captured_at,
+        None => score_info
+            .iter()
+            .flat_map(|item| [&item.update_datetime, &item.highscore_datetime])
+            .filter_map(|time| history::parse_timestamp(time))
+            .max()
+            .ok_or_else(|| {
+                ImportError::Invalid("无法确定捕获时间，成绩中没有游玩时间".to_string())
+            })?,
+    };
+
+    Ok(CaptureRecord {
+        captured_at,
+        scores: songs_score::Data {
+            userid: userid.to_string(),
+            score_info,
+        },
+        partial,
+    })
+}
+
+/// 解析 `scores query --format csv` 导出的文件，按用户 ID 和捕获时间分成多条记录
+fn parse_csv(text: &str, userid: Option<&str>) -> Result<Vec<Imported>, ImportError> {
+    let mut rows = csv_rows(text).into_iter();
+    let header = rows.next().ok_or(ImportError::UnknownFormat)?;
+    let column = |name: &str| header.iter().position(|x| x.trim() == name);
+    let (Some(song_no), Some(level)) = (column("song_no"), column("level")) else {
+        return Err(ImportError::UnknownFormat);
+    };
+
+    let mut groups = BTreeMap::<(String, Option<i64>, bool), Vec<ScoreInfo>>::new();
+    for (index, row) in rows.enumerate() {
+        if row.iter().all(|x| x.is_empty()) {
+            continue;
+        }
+        let line = index + 2;
+        let text = |name: &str| {
+            column(name)
+                .and_then(|i| row.get(i))
+                .map(|x| x.trim())
+                .unwrap_or_default()
+        };
+        let number = |name: &str| -> Result<i64, ImportError> {
+            match text(name) {
+                "" => Ok(0),
+                value => value.parse().map_err(|_| {
+                    ImportError::Invalid(format!("第 {line} 行的 {name} 不是数字：{value}"))
+                }),
+            }
+        };
+
+        let mut item = ScoreInfo {
+            song_no: row
+                .get(song_no)
+                .and_then(|x| x.trim().parse().ok())
+                .ok_or_else(|| ImportError::Invalid(format!("第 {line} 行缺少歌曲编号")))?,
+            level: row
+                .get(level)
+                .and_then(|x| x.trim().parse().ok())
+                .ok_or_else(|| ImportError::Invalid(format!("第 {line} 行缺少难度")))?,
+            high_score: number("high_score")?,
+            best_score_rank: number("best_score_rank")?,
+            good_cnt: number("good_cnt")?,
+            ok_cnt: number("ok_cnt")?,
+            ng_cnt: number("ng_cnt")?,
+            pound_cnt: number("pound_cnt")?,
+            combo_cnt: number("combo_cnt")?,
+            stage_cnt: number("stage_cnt")?,
+            clear_cnt: number("clear_cnt")?,
+            full_combo_cnt: number("full_combo_cnt")?,
+            dondaful_combo_cnt: number("dondaful_combo_cnt")?,
+            highscore_datetime: text("highscore_datetime").to_string(),
+            update_datetime: text("update_datetime").to_string(),
+            ..Default::default()
+        };
+        let detail = &mut item.song_detail;
+        detail.song_name = text("song_name").to_string();
+        detail.song_name_jp = text("song_name_jp").to_string();
+        detail.family = text("family").to_string();
+        let stars = text("stars").parse().ok().filter(|x| *x > 0);
+        match item.level {
+            1 => detail.level_1 = stars,
+            2 => detail.level_2 = stars,
+            3 => detail.level_3 = stars,
+            4 => detail.level_4 = stars,
+            5 => detail.level_5 = stars,
+            _ => {}
+        }
+
+        let captured_at = match text("captured_at") {
+            "" => None,
+            _ => Some(number("captured_at")?),
+        };
+        let partial = !text("partial").is_empty();
+        groups
+            .entry((text("userid").to_string(), captured_at, partial))
+            .or_default()
+            .push(item);
+    }
+
+    groups
+        .into_iter()
+        .map(|((file_userid, captured_at, partial), score_info)| {
+            Ok(Imported {
+                record: new_record(&file_userid, captured_at, partial, score_info, userid)?,
+                full_details: false,
+            })
+        })
+        .collect()
+}
+
+/// 把部分成绩合并到同一用户在这之前最近的一次捕获记录中，`records` 需要按捕获时间从早到晚排列
+///
+/// 相同的谱面使用导出的成绩，原来的记录中有歌曲信息时保留原来的歌曲信息
+pub fn merge_partial(
+    partial: CaptureRecord,
+    records: &[CaptureRecord],
+) -> Result<CaptureRecord, ImportError> {
+    let base = records
+        .iter()
+        .rev()
+        .find(|record| {
+            record.scores.userid == partial.scores.userid
+                && record.captured_at <= partial.captured_at
+        })
+        .ok_or(ImportError::MissingBaseRecord)?;
+
+    let mut merged = base.clone();
+    merged.captured_at = partial.captured_at;
+    for mut item in partial.scores.score_info {
+        let existing = merged
+            .scores
+            .score_info
+            .iter_mut()
+            .find(|x| x.song_no == item.song_no && x.level == item.level);
+        match existing {
+            Some(existing) => {
+                if !existing.song_detail.is_empty() {
+                    item.song_detail = std::mem::take(&mut existing.song_detail);
+                }
+                *existing = item;
+            }
+            None => merged.scores.score_info.push(item),
+        }
+    }
+    Ok(merged)
+}
+
+/// 保存导入的记录，完整的歌曲信息会合并到歌曲目录中，缺少的歌曲信息用歌曲目录补全
+///
+/// 同一用户同一时间的记录已经存在时不保存并返回 `None`
+pub async fn save(
+    config_dir: &Path,
+    imported: Imported,
+) -> Result<Option<CaptureRecord>, ImportError> {
+    let history = History::new(config_dir);
+    let Imported {
+        mut record,
+        full_details,
+    } = imported;
+    if record.partial {
+        let records = history.load_all().await.map_err(ImportError::Io)?;
+        record = merge_partial(record, &records)?;
+    }
+
+    let catalog = if full_details {
+        crate::update_catalog(config_dir, &record.scores, record.captured_at).await
+    } else {
+        Catalog::load(config_dir).await.ok()
+    };
+    if let Some(catalog) = &catalog {
+        catalog.fill_details(&mut record.scores);
+    }
+
+    let saved = history.save_new(&record).await.map_err(ImportError::Io)?;
+    Ok(saved.map(|_| record))
+}
+
+/// 导入完成后用最近一次捕获记录重新计算目标进度
+pub async fn update_goals(config_dir: &Path) -> Result<(), ImportError> {
+    let history = History::new(config_dir);
+    let Some(latest) = history.latest().await.map_err(ImportError::Io)? else {
+        return Ok(());
+    };
+    let catalog = Catalog::load(config_dir).await.ok();
+    crate::update_goals(
+        config_dir,
+        &latest.scores,
+        catalog.as_ref(),
+        latest.captured_at,
+    )
+    .await;
+    Ok(())
+}
+
+/// 按 RFC 4180 拆分 CSV，支持引号中的逗号、换行和转义的引号
+fn csv_rows(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
@@ -4,165 +4,388 @@
 )]
 
 use std::{
+    collections::HashMap,
     net::{IpAddr, Ipv4Addr, SocketAddr},
-    path::PathBuf,
-    sync::Arc,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     time::Duration,
 };
 
-use gui::{send_msg_to_gui, GuiMessage};
-use http::{Method, Response, Uri};
-use http_body_util::BodyExt;
+use capture::CaptureLimits;
+use catalog::Catalog;
+use clap::Parser;
+use config::Config;
+use goals::GoalState;
+use gui::{GuiMessage, send_msg_to_gui};
+use history::{CaptureRecord, CapturedPart, History};
+use http::{Method, Response, uri::Authority};
+use http_body_util::Full;
 use hudsucker::HttpHandler;
-use os::ProxyConfigs;
-use tokio::sync::mpsc::{Receiver, Sender};
+use hudsucker::hyper_util::rt::TokioIo;
+use os::{
+    CaProblem, CertStore, NoopCertStore, NoopProxy, ProxyConfigs, SystemCertStore, SystemProxy,
+};
+use rules::{CaptureRule, RelayTemplate, RuleAction, RuleSet};
+use stats::Stats;
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{Receiver, Sender},
+};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use upstream::UpstreamProxy;
+mod capture;
+mod card;
+mod catalog;
+mod cli;
+mod config;
+mod goals;
 mod gui;
+mod history;
+mod import;
 mod os;
+mod pac;
+mod query;
+mod rating;
+mod report;
+mod rules;
+mod socks5;
 mod songs_score;
+mod stats;
+mod targets;
+#[cfg(test)]
+mod tests;
+mod upstream;
 
 type OneShotSender = tokio::sync::mpsc::Sender<()>;
 
+const LISTEN_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 7650);
+/// 捕获到分数后在窗口中显示的练习目标数量
+const GUI_TARGET_COUNT: usize = 3;
+/// 同步接口响应头中的同步格式版本
+const RELAY_VERSION_HEADER: &str = "X-Relay-Version";
+
+/// 代理服务器的启动选项
+#[derive(Debug, Clone, Default)]
+struct ProxyOptions {
+    /// 手动指定的上游代理，未指定时使用原有的系统代理
+    upstream_proxy: Option<UpstreamProxy>,
+    /// 使用自动代理配置（PAC）代替全局代理，只有成绩相关的域名会经过本程序
+    pac: bool,
+    /// 额外监听的 SOCKS5 代理端口，与 HTTP 代理使用相同的地址
+    socks5_port: Option<u16>,
+    capture_limits: CaptureLimits,
+    rules: RuleSet,
+}
+
+/// 单个请求的状态，用于在收到响应时找到对应的请求
 #[derive(Debug, Clone, Copy)]
-enum UriType {
-    FetchScore,
-    TaikoSongScore,
+struct PendingRequest {
+    id: u64,
+    /// 匹配到的规则在 [`RuleSet`] 中的序号
+    rule: usize,
 }
 
+/// 规则保存的数据，分数另外保留原始的成绩，用于按照同步规则指定的格式重新生成
 #[derive(Debug, Clone)]
-struct Handler {
-    fetch_score: Uri,
-    taiko_songsscore: Uri,
-
-    current_uri_type: Option<UriType>,
-    fetched_score_response: Arc<tokio::sync::Mutex<Option<String>>>,
-    finished_sx: Option<OneShotSender>,
+struct Slot {
+    value: serde_json::Value,
+    scores: Option<Arc<songs_score::Data>>,
 }
 
-// https://www.baidu.com:443/api/ahfsdafbaqwerhue
-// https://wl-taiko.wahlap.net:443/api/user/profile/songscore
+/// hudsucker 会为每个请求克隆一份 Handler，同一个请求的 `handle_request` 和 `handle_response`
+/// 总是在同一份克隆上调用，因此 `pending_request` 只属于当前请求，其它状态需要通过 `Arc` 共享
+#[derive(Debug, Clone)]
+struct Handler {
+    rules: Arc<RuleSet>,
+
+    pending_request: Option<PendingRequest>,
+    request_counter: Arc<AtomicU64>,
+    /// 规则保存的数据，按照规则中的 `slot` 存放
+    slots: Arc<tokio::sync::Mutex<HashMap<String, Slot>>>,
+    finished_sx: Arc<tokio::sync::Mutex<Option<OneShotSender>>>,
+    upstream_proxy: Option<Arc<UpstreamProxy>>,
+    pac_script: Option<Arc<str>>,
+    capture_limits: CaptureLimits,
+    /// 保存捕获记录和歌曲目录的目录，为空时不保存
+    data_dir: Option<Arc<Path>>,
+}
 
 impl Handler {
-    pub fn new(sx: OneShotSender) -> Self {
-        let fetch_score: Uri = "https://www.baidu.com/api/ahfsdafbaqwerhue"
-            .parse()
-            .unwrap();
-        let taiko_songsscore: Uri = "https://wl-taiko.wahlap.net/api/user/profile/songscore"
-            .parse()
-            .unwrap();
+    pub fn new(
+        sx: OneShotSender,
+        rules: RuleSet,
+        upstream_proxy: Option<UpstreamProxy>,
+        pac_script: Option<String>,
+        capture_limits: CaptureLimits,
+        data_dir: Option<&Path>,
+    ) -> Self {
         Self {
-            fetch_score,
-            taiko_songsscore,
-            current_uri_type: None,
-            fetched_score_response: Default::default(),
-            finished_sx: Some(sx),
+            rules: Arc::new(rules),
+            pending_request: None,
+            request_counter: Default::default(),
+            slots: Default::default(),
+            finished_sx: Arc::new(tokio::sync::Mutex::new(Some(sx))),
+            upstream_proxy: upstream_proxy.map(Arc::new),
+            pac_script: pac_script.map(Into::into),
+            capture_limits,
+            data_dir: data_dir.map(Into::into),
         }
     }
 }
 
-impl HttpHandler for Handler {
-    async fn handle_response(
-        &mut self,
-        _ctx: &hudsucker::HttpContext,
-        res: hudsucker::hyper::Response<hudsucker::Body>,
-    ) -> hudsucker::hyper::Response<hudsucker::Body> {
-        match self.current_uri_type {
-            Some(UriType::FetchScore) => {
-                if let Some(fetched_score_response) =
-                    self.fetched_score_response.lock().await.as_ref()
-                {
-                    tracing::info!("监测到同步接口请求，正在转发捕获到的分数数据");
-                    let fetched_score_response = fetched_score_response.clone();
-
-                    if let Some(sx) = self.finished_sx.take() {
-                        tokio::spawn(async move {
-                            tokio::time::sleep(Duration::from_secs(3)).await;
-                            sx.send(()).await.unwrap();
-                        });
-                    }
-
-                    send_msg_to_gui(GuiMessage::SendingScoreData);
-
-                    return hudsucker::hyper::Response::builder()
-                        .header("Content-Type", "application/json")
-                        .header("X-Data-Fetched", "1")
-                        .status(200)
-                        .version(res.version())
-                        .body(hudsucker::Body::from(fetched_score_response))
-                        .unwrap();
-                } else {
-                    tracing::warn!("监测到同步接口请求，但是并没有获取到任何分数数据，请先从鼓众广场小程序中点击我的分数查询！");
-                };
+impl Handler {
+    /// 读取完整的响应并按照规则保存数据，客户端收到的响应与服务器返回的完全一致
+    async fn capture(
+        &self,
+        request: PendingRequest,
+        rule: &CaptureRule,
+        slot: &str,
+        res: Response<hudsucker::Body>,
+    ) -> Response<hudsucker::Body> {
+        tracing::info!("正在接收{}请求 #{} 的响应数据", rule.name, request.id);
+        let (parts, body) = res.into_parts();
+
+        let raw = match capture::read_body(body, &self.capture_limits).await {
+            Ok(raw) => raw,
+            Err((err, body)) => {
+                report_capture_error(&format!("{}：{}", rule.name, err));
+                return Response::from_parts(parts, body);
             }
-            Some(UriType::TaikoSongScore) => {
-                tracing::info!("正在解析分数数据响应数据");
-                let res = hudsucker::decode_response(res).expect("解析分数响应数据失败");
-
-                let (parts, body) = res.into_parts();
-
-                let body = body.collect().await.unwrap().to_bytes();
-
-                // println!("成功捕获到分数数据 {:?}", body);
-                tracing::info!("成功捕获到分数数据，大小为 {}", body.len());
-
-                let cloned_body = http_body_util::Full::new(body.clone());
-
-                // let data_body = String::from_utf8_lossy(&body).into_owned();
-
-                // tokio::fs::write("score.json", &data_body).await.unwrap();
-
-                match serde_json::from_slice::<songs_score::Response>(&body) {
-                    Ok(score_data) => {
-                        tracing::info!("分数响应数据解析成功，正在生成需要返回的数据");
-
-                        if score_data.status == 0 {
-                            let mut result = Vec::with_capacity(score_data.data.score_info.len());
-
-                            for item in score_data.data.score_info {
-                                result.push(serde_json::Value::Array(Vec::from([
-                                    item.song_no.into(),
-                                    item.level.into(),
-                                    item.high_score.into(),
-                                    item.best_score_rank.into(),
-                                    item.good_cnt.into(),
-                                    item.ok_cnt.into(),
-                                    item.ng_cnt.into(),
-                                    item.pound_cnt.into(),
-                                    item.combo_cnt.into(),
-                                    item.stage_cnt.into(),
-                                    item.clear_cnt.into(),
-                                    item.full_combo_cnt.into(),
-                                    item.dondaful_combo_cnt.into(),
-                                    item.update_datetime.into(),
-                                ])));
-                            }
-
-                            self.fetched_score_response
-                                .lock()
-                                .await
-                                .replace(serde_json::to_string(&result).unwrap());
-
+        };
+
+        match capture::decode_body(&parts.headers, raw.clone(), &self.capture_limits).await {
+            Ok(body) => {
+                tracing::info!("成功捕获到{}的数据，大小为 {}", rule.name, body.len());
+                match rule.process(&body) {
+                    Ok((value, part)) => {
+                        let scores = part
+                            .as_ref()
+                            .map(|CapturedPart::Scores(scores)| Arc::new(scores.clone()));
+                        self.slots
+                            .lock()
+                            .await
+                            .insert(slot.to_string(), Slot { value, scores });
+                        if self.rules.is_relayed(slot) {
                             send_msg_to_gui(GuiMessage::WaitForScoreSync);
-                        } else {
-                            tracing::warn!("分数数据返回状态码不为 0，可能是未登录或者其他错误，响应的错误信息为：{}", score_data.message);
+                        }
+                        if let Some(CapturedPart::Scores(scores)) = part {
+                            let summary = Stats::new(&scores, None).summary();
+                            send_msg_to_gui(GuiMessage::ScoreStats(summary));
+                            let targets = targets::next_targets(&scores, None)
+                                .iter()
+                                .take(GUI_TARGET_COUNT)
+                                .map(ToString::to_string)
+                                .collect();
+                            send_msg_to_gui(GuiMessage::NextTargets(targets));
+                            self.record(scores).await;
                         }
                     }
-                    Err(err) => {
-                        tracing::error!("解析分数数据失败：{}", err);
-                    }
+                    Err(err) => report_capture_error(&err),
                 }
+            }
+            Err(err) => report_capture_error(&format!("{}：{}", rule.name, err)),
+        }
 
-                self.current_uri_type = None;
+        Response::from_parts(parts, Full::new(raw).into())
+    }
 
-                return Response::from_parts(parts, cloned_body.into());
-            }
-            None => {
-                return res;
+    /// 把捕获到的分数保存到历史记录中，并更新歌曲目录和目标进度
+    async fn record(&self, scores: songs_score::Data) {
+        let Some(data_dir) = &self.data_dir else {
+            return;
+        };
+
+        let record = CaptureRecord {
+            captured_at: time::OffsetDateTime::now_utc().unix_timestamp(),
+            scores,
+            partial: false,
+        };
+
+        match History::new(data_dir).save(&record).await {
+            Ok(path) => tracing::info!("已保存捕获记录 {}", path.to_string_lossy()),
+            Err(err) => tracing::warn!("无法保存捕获记录：{}", err),
+        }
+
+        let catalog = update_catalog(data_dir, &record.scores, record.captured_at).await;
+        update_goals(
+            data_dir,
+            &record.scores,
+            catalog.as_ref(),
+            record.captured_at,
+        )
+        .await;
+    }
+
+    /// 用保存的数据代替服务器的响应，没有数据时原样转发
+    async fn respond(
+        &self,
+        request: PendingRequest,
+        rule: &CaptureRule,
+        slot: &str,
+        finish: bool,
+        template: Option<&RelayTemplate>,
+        res: Response<hudsucker::Body>,
+    ) -> Response<hudsucker::Body> {
+        let Some(Slot { value, scores }) = self.slots.lock().await.get(slot).cloned() else {
+            tracing::warn!(
+                "监测到{}请求，但是并没有获取到任何分数数据，请先从鼓众广场小程序中点击我的分数查询！",
+                rule.name
+            );
+            return res;
+        };
+        tracing::info!(
+            "监测到{}请求 #{}，正在转发捕获到的分数数据",
+            rule.name,
+            request.id
+        );
+        let (value, version) = match (template, scores) {
+            (Some(template), Some(scores)) => {
+                tracing::info!("正在按照同步格式 {} 生成数据", template.version());
+                let value = template.render(&scores.score_info);
+                (value, Some(template.version().to_string()))
             }
+            (None, Some(_)) => (value, Some(RelayTemplate::default().version().to_string())),
+            (_, None) => (value, None),
+        };
+
+        if finish && let Some(sx) = self.finished_sx.lock().await.take() {
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(3)).await;
+                sx.send(()).await.unwrap();
+            });
+        }
+
+        send_msg_to_gui(GuiMessage::SendingScoreData);
+
+        let mut builder = Response::builder()
+            .header("Content-Type", "application/json")
+            .header("X-Data-Fetched", "1");
+        if let Some(version) = version {
+            builder = builder.header(RELAY_VERSION_HEADER, version);
+        }
+        builder
+            .status(200)
+            .version(res.version())
+            .body(hudsucker::Body::from(
+                serde_json::to_string(&value).unwrap(),
+            ))
+            .unwrap()
+    }
+}
+
+/// 把捕获到的歌曲信息合并到歌曲目录中并保存，无法读取歌曲目录时返回 `None`
+pub async fn update_catalog(
+    data_dir: &Path,
+    scores: &songs_score::Data,
+    captured_at: i64,
+) -> Option<Catalog> {
+    let mut catalog = match Catalog::load(data_dir).await {
+        Ok(catalog) => catalog,
+        Err(err) => {
+            tracing::warn!("无法读取歌曲目录：{}", err);
+            return None;
+        }
+    };
+
+    let added = catalog.merge(scores, captured_at);
+    match catalog.save(data_dir).await {
+        Ok(()) if !added.is_empty() => tracing::info!("歌曲目录新增了 {} 首歌曲", added.len()),
+        Ok(()) => {}
+        Err(err) => tracing::warn!("无法保存歌曲目录：{}", err),
+    }
+    Some(catalog)
+}
+
+/// 重新计算所有目标的进度，新达成的目标会显示在窗口中
+pub async fn update_goals(
+    data_dir: &Path,
+    scores: &songs_score::Data,
+    catalog: Option<&Catalog>,
+    captured_at: i64,
+) {
+    let config_goals = match Config::load(data_dir).await {
+        Ok(config) => config.goals,
+        Err(err) => {
+            tracing::warn!("无法读取配置文件中的目标：{}", err);
+            Vec::new()
+        }
+    };
+    let mut state = match GoalState::load(data_dir).await {
+        Ok(state) => state,
+        Err(err) => {
+            tracing::warn!("无法读取目标进度：{}", err);
+            return;
         }
+    };
+
+    let newly_met = state.update(&config_goals, scores, catalog, captured_at);
+    for goal in state.all_goals(&config_goals) {
+        if let Some(progress) = state.progress.get(&goal.name) {
+            tracing::info!(
+                "目标「{}」的进度：{}/{}",
+                goal.name,
+                progress.achieved,
+                progress.required
+            );
+        }
+    }
+    if let Err(err) = state.save(data_dir).await {
+        tracing::warn!("无法保存目标进度：{}", err);
+    }
 
-        self.current_uri_type = None;
-        res
+    if newly_met.is_empty() {
+        return;
+    }
+    for goal in &newly_met {
+        tracing::info!("达成了目标「{}」", goal.name);
+    }
+    let names = newly_met.into_iter().map(|goal| goal.name).collect();
+    send_msg_to_gui(GuiMessage::GoalsMet(names));
+}
+
+fn report_capture_error(message: &str) {
+    tracing::error!("{}", message);
+    send_msg_to_gui(GuiMessage::CaptureFailed(message.to_string()));
+}
+
+impl HttpHandler for Handler {
+    async fn handle_response(
+        &mut self,
+        _ctx: &hudsucker::HttpContext,
+        res: hudsucker::hyper::Response<hudsucker::Body>,
+    ) -> hudsucker::hyper::Response<hudsucker::Body> {
+        let Some(request) = self.pending_request.take() else {
+            return res;
+        };
+        let rules = self.rules.clone();
+        let rule = rules.get(request.rule);
+
+        if !rule.matches_content_type(res.headers()) {
+            tracing::debug!(
+                "请求 #{} 的响应类型不符合{}规则，直接转发",
+                request.id,
+                rule.name
+            );
+            return res;
+        }
+
+        match &rule.action {
+            RuleAction::Store { slot } | RuleAction::Transform { slot, .. } => {
+                self.capture(request, rule, slot, res).await
+            }
+            RuleAction::Respond {
+                slot,
+                finish,
+                template,
+            } => {
+                self.respond(request, rule, slot, *finish, template.as_ref(), res)
+                    .await
+            }
+        }
     }
 
     async fn handle_request(
@@ -170,21 +393,107 @@ impl HttpHandler for Handler {
         _ctx: &hudsucker::HttpContext,
         req: http::Request<hudsucker::Body>,
     ) -> hudsucker::RequestOrResponse {
-        if req.uri().host() == self.taiko_songsscore.host()
-            && req.uri().path_and_query() == self.taiko_songsscore.path_and_query()
-            && req.method() == Method::POST
+        // 直接发给代理服务器自身的请求没有主机名
+        if let Some(pac_script) = &self.pac_script
+            && req.method() == Method::GET
+            && req.uri().authority().is_none()
+            && req.uri().path() == pac::PAC_PATH
         {
-            tracing::debug!("检测到分数接口请求: {}", req.uri());
-            self.current_uri_type = Some(UriType::TaikoSongScore);
-        } else if req.uri().host() == self.fetch_score.host() && req.method() == Method::GET {
-            tracing::debug!("检测到成绩同步接口请求");
-            self.current_uri_type = Some(UriType::FetchScore);
-        } else {
-            self.current_uri_type = None;
+            tracing::debug!("正在提供自动代理配置脚本");
+            return Response::builder()
+                .header("Content-Type", pac::PAC_CONTENT_TYPE)
+                .body(hudsucker::Body::from(pac_script.to_string()))
+                .unwrap()
+                .into();
         }
 
+        if req.method() == Method::CONNECT
+            && let Some(upstream_proxy) = &self.upstream_proxy
+            && let Some(authority) = req.uri().authority().cloned()
+            && !self.rules.intercepts(authority.host())
+        {
+            return tunnel_via_upstream(upstream_proxy.clone(), authority, req).into();
+        }
+
+        self.pending_request = self
+            .rules
+            .match_request(req.method(), req.uri())
+            .map(|rule| {
+                let id = self.request_counter.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!(
+                    "检测到需要处理的请求 #{} {}: {}",
+                    id,
+                    self.rules.get(rule).name,
+                    req.uri()
+                );
+                PendingRequest { id, rule }
+            });
+
         req.into()
     }
+
+    async fn should_intercept(
+        &mut self,
+        _ctx: &hudsucker::HttpContext,
+        req: &http::Request<hudsucker::Body>,
+    ) -> bool {
+        let Some(host) = req.uri().host() else {
+            return false;
+        };
+
+        if self.rules.intercepts(host) {
+            true
+        } else {
+            tracing::debug!("直接转发 {} 的连接，不进行解密", host);
+            false
+        }
+    }
+}
+
+/// hudsucker 自带的隧道总是直接连接目标服务器，配置了上游代理时需要自行转发
+fn tunnel_via_upstream(
+    upstream_proxy: Arc<UpstreamProxy>,
+    authority: Authority,
+    req: http::Request<hudsucker::Body>,
+) -> Response<hudsucker::Body> {
+    tokio::spawn(async move {
+        let upgraded = match hudsucker::hyper::upgrade::on(req).await {
+            Ok(upgraded) => upgraded,
+            Err(err) => {
+                tracing::warn!("无法建立 {} 的隧道：{}", authority, err);
+                return;
+            }
+        };
+
+        let port = authority.port_u16().unwrap_or(443);
+        let mut server = match upstream_proxy.connect(authority.host(), port).await {
+            Ok(server) => server,
+            Err(err) => {
+                tracing::warn!(
+                    "无法通过上游代理 {} 连接 {}：{}",
+                    upstream_proxy,
+                    authority,
+                    err
+                );
+                return;
+            }
+        };
+
+        let mut upgraded = TokioIo::new(upgraded);
+        let _ = tokio::io::copy_bidirectional(&mut upgraded, &mut server).await;
+    });
+
+    Response::new(hudsucker::Body::empty())
+}
+
+fn is_own_address(proxy: &UpstreamProxy, listen_addr: SocketAddr) -> bool {
+    let (host, port) = proxy.addr();
+    let is_local = host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip == listen_addr.ip() || ip.is_loopback());
+
+    port == listen_addr.port() && is_local
 }
 
 pub fn get_config_dir() -> PathBuf {
@@ -193,23 +502,88 @@ pub fn get_config_dir() -> PathBuf {
         .join("taiko-score-getter")
 }
 
-async fn proxy_main(sx: Sender<()>, mut rx: Receiver<()>) {
-    let listen_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 7650);
-
+async fn proxy_main(
+    listen_addr: SocketAddr,
+    config_dir: &Path,
+    cert_store: &impl CertStore,
+    system_proxy: &impl SystemProxy,
+    options: ProxyOptions,
+    sx: Sender<()>,
+    mut rx: Receiver<()>,
+) -> Result<(), CaProblem> {
     send_msg_to_gui(GuiMessage::Init);
 
-    let proxy_configs = ProxyConfigs::new().await;
-    proxy_configs
-        .set_proxy(listen_addr.ip().to_string(), listen_addr.port())
-        .await;
+    let listener = TcpListener::bind(listen_addr)
+        .await
+        .expect("无法监听代理服务器端口");
+    let listen_addr = listener.local_addr().expect("无法获取代理服务器地址");
+
+    // 残留的系统代理可能指向本程序自身，不能作为上游代理
+    let upstream_proxy = options
+        .upstream_proxy
+        .or_else(|| system_proxy.upstream_proxy())
+        .filter(|proxy| !is_own_address(proxy, listen_addr));
+    if let Some(upstream_proxy) = &upstream_proxy {
+        tracing::info!("将通过上游代理 {} 转发请求", upstream_proxy);
+    }
+
+    // 在修改系统代理之前准备好证书，证书无法使用时不需要还原代理配置
+    let ca = os::get_ca(config_dir, &options.rules.hosts(), cert_store).await?;
+
+    let pac_script = if options.pac {
+        let pac_url = pac::pac_url(listen_addr);
+        tracing::info!("正在使用自动代理配置 {}", pac_url);
+        system_proxy.set_auto_config(&pac_url).await;
+
+        Some(pac::generate_pac(
+            &options.rules.hosts(),
+            listen_addr,
+            upstream_proxy.as_ref(),
+        ))
+    } else {
+        system_proxy
+            .set_proxy(&listen_addr.ip().to_string(), listen_addr.port())
+            .await;
+        None
+    };
 
     tracing::info!("正在启动代理服务器 {}", listen_addr);
 
+    let socks5_task = match options.socks5_port {
+        Some(port) => {
+            let socks5_listener = TcpListener::bind((listen_addr.ip(), port))
+                .await
+                .expect("无法监听 SOCKS5 代理端口");
+            tracing::info!(
+                "正在启动 SOCKS5 代理服务器 {}",
+                socks5_listener
+                    .local_addr()
+                    .expect("无法获取 SOCKS5 代理服务器地址")
+            );
+            Some(tokio::spawn(socks5::serve(
+                socks5_listener,
+                listen_addr,
+                options.rules.clone(),
+            )))
+        }
+        None => None,
+    };
+
     let proxy = hudsucker::Proxy::builder()
-        .with_addr(listen_addr)
-        .with_ca(os::get_ca().await)
-        .with_rustls_client(rustls::crypto::ring::default_provider())
-        .with_http_handler(Handler::new(sx))
+        .with_listener(listener)
+        .with_ca(ca)
+        .with_client(upstream::client(
+            upstream_proxy.clone(),
+            upstream::tls_config(),
+        ))
+        .with_http_handler(Handler::new(
+            sx,
+            options.rules,
+            upstream_proxy,
+            pac_script,
+            options.capture_limits,
+            Some(config_dir),
+        ))
         .with_graceful_shutdown(async move {
             send_msg_to_gui(GuiMessage::WaitForScoreData);
             tracing::info!("代理服务器已启动！");
@@ -230,19 +604,67 @@ async fn proxy_main(sx: Sender<()>, mut rx: Receiver<()>) {
 
     proxy.start().await.unwrap();
 
+    if let Some(socks5_task) = socks5_task {
+        socks5_task.abort();
+    }
+
     tracing::info!("正在还原代理配置");
-    proxy_configs.recover().await;
+    system_proxy.recover().await;
     tracing::info!("代理配置已还原");
 
     send_msg_to_gui(GuiMessage::Close);
+    Ok(())
+}
+
+async fn run_proxy(
+    sx: Sender<()>,
+    rx: Receiver<()>,
+    dry_run: bool,
+    options: ProxyOptions,
+) -> Result<(), CaProblem> {
+    let config_dir = get_config_dir();
+
+    let result = if dry_run {
+        tracing::info!("正在以试运行模式启动，不会修改系统证书和代理配置");
+        proxy_main(
+            LISTEN_ADDR,
+            &config_dir,
+            &NoopCertStore,
+            &NoopProxy,
+            options,
+            sx,
+            rx,
+        )
+        .await
+    } else {
+        let system_proxy = ProxyConfigs::new().await;
+        proxy_main(
+            LISTEN_ADDR,
+            &config_dir,
+            &SystemCertStore,
+            &system_proxy,
+            options,
+            sx,
+            rx,
+        )
+        .await
+    };
+
+    // 启动失败时保留窗口，让用户看到错误信息
+    if let Err(err) = &result {
+        tracing::error!("无法启动代理服务器：{}", err);
+        send_msg_to_gui(GuiMessage::InitError(err.to_string()));
+        return result;
+    }
 
     #[cfg(target_os = "macos")]
     {
         cacao::appkit::App::terminate();
     }
+    result
 }
 
-fn main() {
+fn main() -> ExitCode {
     let fmt_layer = tracing_subscriber::fmt::layer()
         .with_target(false)
         .without_time()
@@ -255,15 +677,87 @@ fn main() {
         .with(fmt_layer)
         .init();
 
+    // 有参数时可能是命令行子命令，帮助和错误信息也需要输出到控制台
+    #[cfg(target_os = "windows")]
+    if std::env::args_os().len() > 1 {
+        os::attach_parent_console();
+    }
+    let cli = cli::Cli::parse();
+
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .expect("无法创建异步运行时环境");
 
+    match cli.command {
+        Some(cli::Command::Uninstall) => {
+            return rt.block_on(cli::uninstall(cli.dry_run));
+        }
+        Some(cli::Command::Stats { json }) => {
+            return rt.block_on(cli::stats(json));
+        }
+        Some(cli::Command::Rating { formula, json }) => {
+            rt.block_on(cli::rating(formula, json));
+            return ExitCode::SUCCESS;
+        }
+        Some(cli::Command::Card { output, formula }) => {
+            rt.block_on(cli::card(output, formula));
+            return ExitCode::SUCCESS;
+        }
+        Some(cli::Command::Report { output, formula }) => {
+            rt.block_on(cli::report(output, formula));
+            return ExitCode::SUCCESS;
+        }
+        Some(cli::Command::Targets { limit, json }) => {
+            rt.block_on(cli::targets(limit, json));
+            return ExitCode::SUCCESS;
+        }
+        Some(cli::Command::Import { files, userid }) => {
+            return rt.block_on(cli::import(files, userid));
+        }
+        Some(cli::Command::Scores { command }) => {
+            return rt.block_on(cli::scores(command));
+        }
+        Some(cli::Command::Goals { command }) => {
+            return rt.block_on(cli::goals(command.unwrap_or(cli::GoalsCommand::List)));
+        }
+        Some(cli::Command::Songs { command }) => {
+            rt.block_on(cli::songs(command));
+            return ExitCode::SUCCESS;
+        }
+        _ => {}
+    }
+
+    let rules = match rt.block_on(config::load_rules(&get_config_dir())) {
+        Ok(rules) => rules,
+        Err(err) => {
+            tracing::error!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(cli::Command::Cert { command }) = cli.command {
+        return rt.block_on(cli::cert(command, &rules, cli.dry_run));
+    }
+
     let _guard = rt.enter();
     let (sx, rx) = tokio::sync::mpsc::channel(1);
-    let task = rt.spawn(proxy_main(sx.clone(), rx));
+    let task = rt.spawn(run_proxy(
+        sx.clone(),
+        rx,
+        cli.dry_run,
+        ProxyOptions {
+            capture_limits: cli.capture_limits(),
+            upstream_proxy: cli.upstream_proxy,
+            pac: cli.pac,
+            socks5_port: cli.socks5_port,
+            rules,
+        },
+    ));
 
     gui::init_gui(rt.handle(), sx);
-    rt.block_on(task).unwrap();
+    match rt.block_on(task).unwrap() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(_) => ExitCode::FAILURE,
+    }
 }
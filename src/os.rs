@@ -1,119 +1,468 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
 use hudsucker::{certificate_authority::RcgenAuthority, rcgen::*};
+use ring::rand::{SecureRandom, SystemRandom};
+use time::{Duration, OffsetDateTime};
+
+use crate::upstream::UpstreamProxy;
 
 // TODO: 其他系统支持
 #[cfg(target_os = "windows")]
 mod windows;
 #[cfg(target_os = "windows")]
-pub use windows::ProxyConfigs;
-#[cfg(target_os = "windows")]
-use windows::*;
+pub use windows::{ProxyConfigs, SystemCertStore, attach_parent_console};
 
 #[cfg(target_os = "macos")]
 mod macos;
 #[cfg(target_os = "macos")]
-pub use macos::ProxyConfigs;
-#[cfg(target_os = "macos")]
-use macos::*;
+pub use macos::{ProxyConfigs, SystemCertStore};
+
+pub mod key_file;
+use key_file::PASSPHRASE_ENV;
+
+mod status;
+pub use status::{CaProblem, check_ca};
+
+mod noop;
+pub use noop::{NoopCertStore, NoopProxy};
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub use noop::{NoopCertStore as SystemCertStore, NoopProxy as ProxyConfigs};
+
+#[cfg(test)]
+mod fake;
+#[cfg(test)]
+pub use fake::{FakeCertStore, FakeProxy};
+
+/// 证书名称的前缀，每次生成的证书都会在后面加上随机的后缀以便区分
+///
+/// 旧版本生成的证书名称没有后缀，即为此前缀本身
+pub const CERT_NAME_PREFIX: &str = "Taiko Score Getter Certificate";
+
+/// 新生成的 CA 证书的有效期
+const CA_VALIDITY_DAYS: i64 = 730;
+
+/// 系统证书库，负责安装和检查代理所使用的 CA 证书，证书通过其名称（CN）区分
+pub trait CertStore: Send + Sync {
+    fn is_cert_installed(&self, cert_name: &str) -> impl Future<Output = bool> + Send;
+    fn is_cert_trusted(
+        &self,
+        cert_name: &str,
+        cer_path: &Path,
+    ) -> impl Future<Output = bool> + Send;
+    fn install_cert(&self, cert_name: &str, cer_path: &Path) -> impl Future<Output = ()> + Send;
+    /// 从证书库中移除证书，返回是否确实移除了证书
+    fn uninstall_cert(&self, cert_name: &str, cer_path: &Path)
+    -> impl Future<Output = bool> + Send;
+}
+
+/// 系统代理配置，负责在代理运行期间设置并在结束后还原
+pub trait SystemProxy: Send + Sync {
+    fn set_proxy(&self, proxy_host: &str, proxy_port: u16) -> impl Future<Output = ()> + Send;
+    /// 将系统的自动代理配置（PAC）地址设置为指定地址，不修改全局代理
+    fn set_auto_config(&self, pac_url: &str) -> impl Future<Output = ()> + Send;
+    fn recover(&self) -> impl Future<Output = ()> + Send;
+    /// 如果系统代理或自动代理配置仍然指向指定地址（例如程序异常退出后残留的配置），则将其关闭，返回是否进行了还原
+    fn remove_leftover_proxy(
+        &self,
+        proxy_host: &str,
+        proxy_port: u16,
+    ) -> impl Future<Output = bool> + Send;
+    /// 设置系统代理之前正在使用的代理，会作为上游代理转发请求
+    fn upstream_proxy(&self) -> Option<UpstreamProxy>;
+}
 
 pub(super) fn generate_key_pair() -> KeyPair {
     KeyPair::generate().expect("无法生成证书密钥对")
 }
 
-pub async fn get_ca() -> RcgenAuthority {
-    let config_path = crate::get_config_dir();
+fn random_cert_name() -> String {
+    let mut suffix = [0u8; 4];
+    SystemRandom::new()
+        .fill(&mut suffix)
+        .expect("无法生成随机数");
+
+    let suffix: String = suffix.iter().map(|b| format!("{b:02X}")).collect();
+    format!("{CERT_NAME_PREFIX} {suffix}")
+}
+
+/// 读取证书的名称（CN）
+pub fn cert_name(params: &CertificateParams) -> Option<String> {
+    match params.distinguished_name.get(&DnType::CommonName)? {
+        DnValue::Utf8String(name) => Some(name.clone()),
+        DnValue::PrintableString(name) => Some(name.as_str().to_string()),
+        DnValue::Ia5String(name) => Some(name.as_str().to_string()),
+        _ => None,
+    }
+}
+
+/// 生成一对新的自签名 CA 证书和密钥对，不会写入任何文件
+///
+/// 证书的有效期有限，并通过名称约束限制为只能给 `permitted_hosts` 中的域名签发证书，
+/// 即便私钥泄露也无法用于伪造其他网站的证书
+pub fn generate_ca(permitted_hosts: &[&str]) -> (Certificate, KeyPair) {
+    let key_pair = generate_key_pair();
+
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, random_cert_name());
+
+    let now = OffsetDateTime::now_utc();
+
+    let mut cert_param = CertificateParams::default();
+    cert_param.distinguished_name = distinguished_name;
+    cert_param.not_before = now - Duration::days(1);
+    cert_param.not_after = now + Duration::days(CA_VALIDITY_DAYS);
+    cert_param.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    cert_param.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    cert_param.name_constraints = Some(NameConstraints {
+        permitted_subtrees: permitted_hosts
+            .iter()
+            .map(|host| GeneralSubtree::DnsName(host.to_string()))
+            .collect(),
+        excluded_subtrees: Vec::new(),
+    });
+
+    let cert = cert_param
+        .self_signed(&key_pair)
+        .expect("无法生成随机自签证书");
+
+    (cert, key_pair)
+}
+
+/// 将私钥文件的访问权限限制为仅当前用户（Windows 下为管理员）可读写
+async fn protect_key_file(key_path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        tokio::fs::set_permissions(key_path, std::fs::Permissions::from_mode(0o600)).await?;
+    }
+
+    #[cfg(target_os = "windows")]
+    windows::protect_key_file(key_path).await?;
+
+    Ok(())
+}
+
+/// 私钥文件是否还允许当前用户（Windows 下为管理员）以外的账户访问
+async fn is_key_file_exposed(key_path: &Path) -> io::Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = tokio::fs::metadata(key_path).await?.permissions().mode();
+        Ok(mode & 0o077 != 0)
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        windows::is_key_file_inherited(key_path).await
+    }
+
+    #[cfg(not(any(unix, target_os = "windows")))]
+    {
+        let _ = key_path;
+        Ok(false)
+    }
+}
+
+/// 旧版本写入的私钥文件使用默认权限，启动时检查并收紧，只在需要时修改，失败时只记录日志
+async fn migrate_key_permissions(key_path: &Path) {
+    match is_key_file_exposed(key_path).await {
+        Ok(false) => {}
+        Ok(true) => match protect_key_file(key_path).await {
+            Ok(()) => tracing::info!("已收紧私钥文件 {} 的访问权限", key_path.to_string_lossy()),
+            Err(err) => tracing::warn!(
+                "无法收紧私钥文件 {} 的访问权限：{}",
+                key_path.to_string_lossy(),
+                err
+            ),
+        },
+        Err(err) => tracing::warn!(
+            "无法检查私钥文件 {} 的访问权限：{}",
+            key_path.to_string_lossy(),
+            err
+        ),
+    }
+}
+
+async fn write_key_file(key_path: &Path, key_pair: &KeyPair) -> io::Result<()> {
+    let mut contents = key_pair.serialize_pem();
+
+    if let Some(passphrase) = std::env::var(PASSPHRASE_ENV).ok().filter(|x| !x.is_empty()) {
+        tracing::info!("正在使用口令加密私钥文件");
+        contents = key_file::encrypt(&contents, &passphrase);
+    }
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(key_path).await?;
+    tokio::io::AsyncWriteExt::write_all(&mut file, contents.as_bytes()).await?;
+
+    protect_key_file(key_path).await
+}
+
+async fn read_key_file(key_path: &Path) -> Result<KeyPair, CaProblem> {
+    let mut key_data = tokio::fs::read_to_string(key_path)
+        .await
+        .map_err(|_| CaProblem::Missing)?;
+
+    if key_file::is_encrypted(&key_data) {
+        let passphrase = std::env::var(PASSPHRASE_ENV).map_err(|_| CaProblem::KeyLocked)?;
+        key_data = key_file::decrypt(&key_data, &passphrase).ok_or(CaProblem::KeyLocked)?;
+    }
+
+    KeyPair::from_pem(&key_data).map_err(|_| CaProblem::KeyMismatch)
+}
+
+/// 更换证书的结果
+#[derive(Debug)]
+pub struct RotationReport {
+    pub cert_name: String,
+    pub removed_certs: Vec<String>,
+}
+
+/// 生成新的证书并替换证书文件，然后卸载旧的证书并安装新的证书
+///
+/// 先替换文件再修改证书库，之后的步骤中断时下次启动会重新安装证书文件中的证书；
+/// 替换文件之前中断时留下的 `.new` 文件由 [`get_ca`] 处理
+pub async fn rotate_ca(
+    config_path: &Path,
+    permitted_hosts: &[&str],
+    cert_store: &impl CertStore,
+) -> io::Result<RotationReport> {
     let cer_path = config_path.join("ca.cer");
     let key_path = config_path.join("ca.key");
+    let new_cer_path = config_path.join("ca.cer.new");
+    let new_key_path = config_path.join("ca.key.new");
 
-    let (cert, key_pair) = if cer_path.as_path().exists() && key_path.as_path().exists() {
-        tracing::info!(
-            "正在使用已有签名证书文件 {}",
-            cer_path.as_path().to_string_lossy()
-        );
-        tracing::info!(
-            "正在使用已有签名私钥文件 {}",
-            key_path.as_path().to_string_lossy()
-        );
-        let key_data = tokio::fs::read_to_string(key_path)
-            .await
-            .expect("无法读取已有的私钥文件");
-        let key_pair = KeyPair::from_pem(&key_data).expect("无法解析已有的私钥文件");
-        let cer_data = tokio::fs::read_to_string(cer_path)
-            .await
-            .expect("无法读取已有的签名证书文件");
-
-        (
-            CertificateParams::from_ca_cert_pem(&cer_data)
-                .expect("无法解析已有的签名文件")
-                .self_signed(&key_pair)
-                .expect("无法对此证书自签名"),
-            key_pair,
-        )
-    } else {
-        tracing::warn!(
-            "正在生成新的密钥对到 {}",
-            config_path.as_path().to_string_lossy()
-        );
-        let key_pair = generate_key_pair();
+    let old_cert_names = installed_cert_names(&cer_path).await;
 
-        let mut distinguished_name = DistinguishedName::new();
-        distinguished_name.push(DnType::CommonName, "Taiko Score Getter Certificate");
+    tracing::warn!("正在生成新的密钥对到 {}", config_path.to_string_lossy());
+    let (cert, key_pair) = generate_ca(permitted_hosts);
+    let cert_name = cert_name(cert.params()).expect("新生成的证书没有名称");
 
-        let mut cert_param = CertificateParams::default();
-        cert_param.distinguished_name = distinguished_name;
-        cert_param.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    tokio::fs::create_dir_all(config_path).await?;
+    tokio::fs::write(&new_cer_path, cert.pem()).await?;
+    write_key_file(&new_key_path, &key_pair).await?;
 
-        let cert = cert_param
-            .self_signed(&key_pair)
-            .expect("无法生成随机自签证书");
+    // 先替换证书文件再替换私钥文件，中断时可以根据剩下的 `.new` 文件判断进行到了哪一步
+    tokio::fs::rename(&new_cer_path, &cer_path).await?;
+    tokio::fs::rename(&new_key_path, &key_path).await?;
 
-        tokio::fs::create_dir_all(&config_path)
-            .await
-            .expect("无法创建配置目录");
-        tokio::fs::write(&cer_path, cert.pem())
-            .await
-            .expect("无法保存证书文件");
-        tokio::fs::write(&key_path, key_pair.serialize_pem())
-            .await
-            .expect("无法保存密钥对 PEM 文件");
+    tracing::info!(
+        " PEM 密钥对已写入至 {}",
+        key_path.as_path().to_string_lossy()
+    );
+    tracing::info!(
+        " CER 证书已写入至   {}",
+        cer_path.as_path().to_string_lossy()
+    );
+
+    // 先卸载旧证书再安装新证书，部分平台按名称子串查找证书，旧版本的证书名称是新证书名称的前缀
+    let mut removed_certs = Vec::new();
+    for old_cert_name in old_cert_names {
+        if cert_store.uninstall_cert(&old_cert_name, &cer_path).await {
+            tracing::info!("已从证书库中移除旧证书 {}", old_cert_name);
+            removed_certs.push(old_cert_name);
+        }
+    }
+
+    cert_store.install_cert(&cert_name, &cer_path).await;
+
+    Ok(RotationReport {
+        cert_name,
+        removed_certs,
+    })
+}
+
+/// 处理上次更换证书中断时留下的 `.new` 文件
+///
+/// 证书文件还没有替换时放弃这次更换；只剩私钥文件时证书文件已经替换，继续替换私钥文件
+async fn recover_rotation(config_path: &Path) -> io::Result<()> {
+    let key_path = config_path.join("ca.key");
+    let new_cer_path = config_path.join("ca.cer.new");
+    let new_key_path = config_path.join("ca.key.new");
+
+    if tokio::fs::try_exists(&new_cer_path).await? {
+        tracing::warn!("上次更换证书没有完成，正在删除新生成的证书文件");
+        for path in [&new_cer_path, &new_key_path] {
+            match tokio::fs::remove_file(path).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+    } else if tokio::fs::try_exists(&new_key_path).await? {
+        tracing::warn!("上次更换证书没有完成，正在替换私钥文件");
+        tokio::fs::rename(&new_key_path, &key_path).await?;
+    }
+    Ok(())
+}
+
+/// 证书文件对应的证书名称，以及旧版本使用的没有后缀的证书名称
+async fn installed_cert_names(cer_path: &Path) -> Vec<String> {
+    let mut cert_names = vec![CERT_NAME_PREFIX.to_string()];
+    if let Some(name) = tokio::fs::read_to_string(cer_path)
+        .await
+        .ok()
+        .and_then(|data| CertificateParams::from_ca_cert_pem(&data).ok())
+        .and_then(|params| cert_name(&params))
+    {
+        cert_names.insert(0, name);
+    }
+    cert_names.dedup();
+    cert_names
+}
+
+/// 读取或重新生成 CA 证书，私钥已加密但是没有提供正确的口令时返回 [`CaProblem::KeyLocked`]，
+/// 无法写入新的证书文件时返回 [`CaProblem::FileError`]
+pub async fn get_ca(
+    config_path: &Path,
+    permitted_hosts: &[&str],
+    cert_store: &impl CertStore,
+) -> Result<RcgenAuthority, CaProblem> {
+    let cer_path = config_path.join("ca.cer");
+    let key_path = config_path.join("ca.key");
+
+    recover_rotation(config_path)
+        .await
+        .map_err(|err| CaProblem::FileError(err.to_string()))?;
+    let status = check_ca(config_path, permitted_hosts, cert_store).await;
+
+    if status.problems.contains(&CaProblem::KeyLocked) {
+        return Err(CaProblem::KeyLocked);
+    }
 
+    if status.needs_rotation() {
+        for problem in status.problems.iter().filter(|x| x.needs_rotation()) {
+            tracing::warn!("{}，需要重新生成证书", problem);
+        }
+        rotate_ca(config_path, permitted_hosts, cert_store)
+            .await
+            .map_err(|err| CaProblem::FileError(err.to_string()))?;
+    } else {
         tracing::info!(
-            " PEM 密钥对已写入至 {}",
-            key_path.as_path().to_string_lossy()
+            "正在使用已有签名证书文件 {}",
+            cer_path.as_path().to_string_lossy()
         );
         tracing::info!(
-            " CER 证书已写入至   {}",
-            cer_path.as_path().to_string_lossy()
+            "正在使用已有签名私钥文件 {}",
+            key_path.as_path().to_string_lossy()
         );
+        migrate_key_permissions(&key_path).await;
+    }
 
-        (cert, key_pair)
-    };
+    let key_pair = read_key_file(&key_path)
+        .await
+        .expect("无法读取已有的私钥文件");
+    let cer_data = tokio::fs::read_to_string(&cer_path)
+        .await
+        .expect("无法读取已有的签名证书文件");
+    let cert = CertificateParams::from_ca_cert_pem(&cer_data)
+        .expect("无法解析已有的签名文件")
+        .self_signed(&key_pair)
+        .expect("无法对此证书自签名");
+
+    let cert_name = cert_name(cert.params()).unwrap_or_else(|| CERT_NAME_PREFIX.to_string());
 
-    if !is_cert_installed().await {
-        install_cert().await;
+    if !cert_store.is_cert_installed(&cert_name).await {
+        cert_store.install_cert(&cert_name, &cer_path).await;
     }
 
-    if !is_cert_trusted().await {
+    if !cert_store.is_cert_trusted(&cert_name, &cer_path).await {
         tracing::warn!("证书已经安装但未信任，请按照提示操作");
         #[cfg(target_os = "macos")]
         {
             tracing::warn!("证书已安装成功，还有最后一步信任证书需要操作：");
-            tracing::warn!("  1. 打开 钥匙串访问 程序，找到 Taiko Score Getter Certificate 证书");
+            tracing::warn!("  1. 打开 钥匙串访问 程序，找到 {} 证书", cert_name);
             tracing::warn!(
-                "  2. 在右上角搜索 Taiko Score Getter Certificate 证书，并双击打开搜索到的证书"
+                "  2. 在右上角搜索 {} 证书，并双击打开搜索到的证书",
+                cert_name
             );
             tracing::warn!("  3. 展开 信任 栏目，将 使用此证书时 下拉框配置为 完全信任");
-            tracing::warn!("  详情可以参考 https://github.com/Steve-xmh/taiko-score-getter-rs/blob/main/MACOS.md");
+            tracing::warn!(
+                "  详情可以参考 https://github.com/Steve-xmh/taiko-score-getter-rs/blob/main/MACOS.md"
+            );
         }
         crate::gui::send_msg_to_gui(crate::gui::GuiMessage::CertTrustNeeded);
-        while !is_cert_trusted().await {
+        while !cert_store.is_cert_trusted(&cert_name, &cer_path).await {
             tokio::time::sleep(std::time::Duration::from_secs(3)).await;
         }
     }
 
-    RcgenAuthority::new(
+    Ok(RcgenAuthority::new(
         key_pair,
         cert,
         1000,
         rustls::crypto::ring::default_provider(),
-    )
+    ))
+}
+
+/// 卸载时实际清理掉的内容
+#[derive(Debug, Default)]
+pub struct UninstallReport {
+    pub proxy_restored: bool,
+    pub removed_certs: Vec<String>,
+    pub removed_files: Vec<PathBuf>,
+    /// 无法删除的文件以及原因，例如文件被占用或者只读
+    pub failed_files: Vec<(PathBuf, String)>,
+}
+
+/// 移除证书库中的证书、删除证书和私钥文件（包括更换证书中断时留下的新文件），并还原残留的系统代理配置
+pub async fn uninstall(
+    config_path: &Path,
+    listen_addr: SocketAddr,
+    cert_store: &impl CertStore,
+    system_proxy: &impl SystemProxy,
+) -> UninstallReport {
+    let cer_path = config_path.join("ca.cer");
+    let key_path = config_path.join("ca.key");
+
+    let mut report = UninstallReport {
+        proxy_restored: system_proxy
+            .remove_leftover_proxy(&listen_addr.ip().to_string(), listen_addr.port())
+            .await,
+        ..Default::default()
+    };
+
+    // 同时清理旧版本安装的没有后缀的证书
+    let cert_names = installed_cert_names(&cer_path).await;
+
+    for cert_name in &cert_names {
+        if cert_store.uninstall_cert(cert_name, &cer_path).await {
+            tracing::info!("已从证书库中移除 {}", cert_name);
+            report.removed_certs.push(cert_name.clone());
+        }
+    }
+
+    let new_key_path = config_path.join("ca.key.new");
+    let new_cer_path = config_path.join("ca.cer.new");
+    for path in [key_path, cer_path, new_key_path, new_cer_path] {
+        if !path.exists() {
+            continue;
+        }
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {
+                tracing::info!("已删除 {}", path.to_string_lossy());
+                report.removed_files.push(path);
+            }
+            Err(err) => {
+                tracing::warn!("无法删除 {}：{}", path.to_string_lossy(), err);
+                report.failed_files.push((path, err.to_string()));
+            }
+        }
+    }
+
+    report
 }
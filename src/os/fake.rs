@@ -0,0 +1,132 @@
+use std::{
+    path::Path,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use super::{CertStore, SystemProxy};
+use crate::upstream::UpstreamProxy;
+
+/// 仅存在于内存中的证书库，安装后即视为已信任
+#[derive(Debug, Default)]
+pub struct FakeCertStore {
+    installed: Mutex<Vec<String>>,
+    install_count: AtomicUsize,
+}
+
+impl FakeCertStore {
+    pub fn install_count(&self) -> usize {
+        self.install_count.load(Ordering::SeqCst)
+    }
+
+    pub fn installed(&self) -> Vec<String> {
+        self.installed.lock().unwrap().clone()
+    }
+}
+
+impl CertStore for FakeCertStore {
+    async fn is_cert_installed(&self, cert_name: &str) -> bool {
+        self.installed
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|x| x == cert_name)
+    }
+
+    async fn is_cert_trusted(&self, cert_name: &str, _cer_path: &Path) -> bool {
+        self.is_cert_installed(cert_name).await
+    }
+
+    async fn install_cert(&self, cert_name: &str, cer_path: &Path) {
+        assert!(cer_path.exists(), "安装证书前证书文件必须已经写入");
+        self.installed.lock().unwrap().push(cert_name.to_string());
+        self.install_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    async fn uninstall_cert(&self, cert_name: &str, _cer_path: &Path) -> bool {
+        let mut installed = self.installed.lock().unwrap();
+        let len = installed.len();
+        installed.retain(|x| x != cert_name);
+        installed.len() != len
+    }
+}
+
+/// 仅存在于内存中的系统代理配置
+#[derive(Debug, Default)]
+pub struct FakeProxy {
+    current: Mutex<Option<(String, u16)>>,
+    auto_config: Mutex<Option<String>>,
+    recover_count: AtomicUsize,
+    previous: Option<UpstreamProxy>,
+}
+
+impl FakeProxy {
+    /// 模拟设置系统代理之前已经在使用其它代理
+    pub fn with_previous(previous: UpstreamProxy) -> Self {
+        Self {
+            previous: Some(previous),
+            ..Default::default()
+        }
+    }
+
+    pub fn current(&self) -> Option<(String, u16)> {
+        self.current.lock().unwrap().clone()
+    }
+
+    pub fn auto_config(&self) -> Option<String> {
+        self.auto_config.lock().unwrap().clone()
+    }
+
+    pub fn recover_count(&self) -> usize {
+        self.recover_count.load(Ordering::SeqCst)
+    }
+}
+
+impl SystemProxy for FakeProxy {
+    async fn set_proxy(&self, proxy_host: &str, proxy_port: u16) {
+        self.current
+            .lock()
+            .unwrap()
+            .replace((proxy_host.to_string(), proxy_port));
+    }
+
+    async fn set_auto_config(&self, pac_url: &str) {
+        self.auto_config
+            .lock()
+            .unwrap()
+            .replace(pac_url.to_string());
+    }
+
+    async fn recover(&self) {
+        self.current.lock().unwrap().take();
+        self.auto_config.lock().unwrap().take();
+        self.recover_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    async fn remove_leftover_proxy(&self, proxy_host: &str, proxy_port: u16) -> bool {
+        let mut restored = false;
+
+        let mut current = self.current.lock().unwrap();
+        if current.as_ref() == Some(&(proxy_host.to_string(), proxy_port)) {
+            current.take();
+            restored = true;
+        }
+
+        let mut auto_config = self.auto_config.lock().unwrap();
+        if auto_config
+            .as_ref()
+            .is_some_and(|url| url.starts_with(&format!("http://{proxy_host}:{proxy_port}/")))
+        {
+            auto_config.take();
+            restored = true;
+        }
+
+        restored
+    }
+
+    fn upstream_proxy(&self) -> Option<UpstreamProxy> {
+        self.previous.clone()
+    }
+}
@@ -0,0 +1,72 @@
+use std::num::NonZeroU32;
+
+use ring::{
+    aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+
+/// 设置此环境变量后，私钥文件会使用其中的口令加密保存
+pub const PASSPHRASE_ENV: &str = "TAIKO_SCORE_GETTER_KEY_PASSPHRASE";
+
+const ENCRYPTED_KEY_TAG: &str = "TAIKO SCORE GETTER ENCRYPTED PRIVATE KEY";
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: NonZeroU32 = NonZeroU32::new(100_000).unwrap();
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        PBKDF2_ITERATIONS,
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).expect("无法创建私钥加密密钥"))
+}
+
+pub fn is_encrypted(data: &str) -> bool {
+    data.contains(ENCRYPTED_KEY_TAG)
+}
+
+/// 使用口令加密 PEM 格式的私钥，结果为 盐 + 随机数 + 密文 的 PEM 块
+pub fn encrypt(key_pem: &str, passphrase: &str) -> String {
+    let rng = SystemRandom::new();
+    let mut salt = [0; SALT_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    rng.fill(&mut salt).expect("无法生成随机数");
+    rng.fill(&mut nonce).expect("无法生成随机数");
+
+    let mut data = key_pem.as_bytes().to_vec();
+    derive_key(passphrase, &salt)
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .expect("无法加密私钥");
+
+    pem::encode(&pem::Pem::new(
+        ENCRYPTED_KEY_TAG,
+        [&salt[..], &nonce[..], &data].concat(),
+    ))
+}
+
+/// 解密 [`encrypt`] 生成的私钥，口令错误或者文件损坏时返回 `None`
+pub fn decrypt(data: &str, passphrase: &str) -> Option<String> {
+    let data = pem::parse(data).ok()?;
+    if data.tag() != ENCRYPTED_KEY_TAG || data.contents().len() < SALT_LEN + NONCE_LEN {
+        return None;
+    }
+
+    let (salt, rest) = data.contents().split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let mut ciphertext = ciphertext.to_vec();
+
+    let plaintext = derive_key(passphrase, salt)
+        .open_in_place(
+            Nonce::try_assume_unique_for_key(nonce).ok()?,
+            Aad::empty(),
+            &mut ciphertext,
+        )
+        .ok()?;
+
+    String::from_utf8(plaintext.to_vec()).ok()
+}
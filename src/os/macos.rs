@@ -1,48 +1,172 @@
-pub async fn is_cert_installed() -> bool {
+use std::path::{Path, PathBuf};
+
+use super::{CertStore, SystemProxy};
+use crate::upstream::UpstreamProxy;
+
+/// 通过 `security` 管理的系统钥匙串
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemCertStore;
+
+impl CertStore for SystemCertStore {
+    async fn is_cert_installed(&self, cert_name: &str) -> bool {
+        !find_cert_hashes(cert_name).await.is_empty()
+    }
+
+    async fn is_cert_trusted(&self, _cert_name: &str, cer_path: &Path) -> bool {
+        let p = tokio::process::Command::new("security")
+            .arg("verify-cert")
+            .arg("-c")
+            .arg(cer_path)
+            .output()
+            .await
+            .expect("无法检查证书是否已信任");
+
+        p.status.success()
+    }
+
+    async fn install_cert(&self, _cert_name: &str, cer_path: &Path) {
+        let p = tokio::process::Command::new("osascript")
+        .arg("-e")
+        .arg(format!(
+            r#"do shell script "security authorizationdb write com.apple.trust-settings.admin allow ; security add-trusted-cert -d -r trustAsRoot -p ssl -p basic -s \"localhost\" -k /Library/Keychains/System.keychain \"{}\" ; security authorizationdb remove com.apple.trust-settings.admin" with prompt "太鼓成绩提取器需要安装代理证书" with administrator privileges"#,
+            cer_path.to_string_lossy()
+        ))
+        .output()
+        .await
+        .expect("无法安装证书");
+
+        if !p.status.success() {
+            panic!("证书安装失败");
+        }
+    }
+
+    async fn uninstall_cert(&self, cert_name: &str, _cer_path: &Path) -> bool {
+        let hashes = find_cert_hashes(cert_name).await;
+        if hashes.is_empty() {
+            return false;
+        }
+
+        // 信任设置和证书都按 SHA-1 找到的证书处理，避免误删名称以此为前缀的其它证书，
+        // 旧版本的证书和当前的证书文件不是同一个证书，不能通过证书文件撤销信任设置
+        let exported = export_certs(cert_name, &hashes).await;
+        let commands = exported
+            .iter()
+            .map(|path| {
+                format!(
+                    r#"security remove-trusted-cert -d \"{}\""#,
+                    path.to_string_lossy()
+                )
+            })
+            .chain(hashes.iter().map(|hash| {
+                format!(
+                    "security delete-certificate -Z {hash} -t /Library/Keychains/System.keychain"
+                )
+            }))
+            .collect::<Vec<_>>()
+            .join(" ; ");
+
+        let p = tokio::process::Command::new("osascript")
+        .arg("-e")
+        .arg(format!(
+            r#"do shell script "{}" with prompt "太鼓成绩提取器需要卸载代理证书" with administrator privileges"#,
+            commands
+        ))
+        .output()
+        .await
+        .expect("无法卸载证书");
+
+        for path in exported {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+
+        if !p.status.success() {
+            tracing::warn!("证书卸载失败：{}", String::from_utf8_lossy(&p.stderr));
+        }
+
+        !self.is_cert_installed(cert_name).await
+    }
+}
+
+/// 查找名称完全一致的证书的 SHA-1 值，`security find-certificate -c` 只会按子串匹配
+async fn find_cert_hashes(cert_name: &str) -> Vec<String> {
     let p = tokio::process::Command::new("security")
         .arg("find-certificate")
+        .arg("-a")
+        .arg("-Z")
         .arg("-c")
-        .arg("Taiko Score Getter Certificate")
-        .arg("-p")
+        .arg(cert_name)
         .output()
         .await
         .expect("无法检查证书是否已安装");
 
-    p.status.success()
-}
+    if !p.status.success() {
+        return Vec::new();
+    }
 
-pub async fn is_cert_trusted() -> bool {
-    let config_path = crate::get_config_dir();
-    let cer_path = config_path.as_path().join("ca.cer");
+    let label = format!(r#""labl"<blob>="{}""#, cert_name);
+    let mut hashes = Vec::new();
+    let mut current_hash = None;
+    for line in String::from_utf8_lossy(&p.stdout).lines() {
+        let line = line.trim();
+        if let Some(hash) = line.strip_prefix("SHA-1 hash:") {
+            current_hash = Some(hash.trim().to_string());
+        } else if line == label {
+            if let Some(hash) = current_hash.take() {
+                hashes.push(hash);
+            }
+        }
+    }
+    hashes
+}
 
+/// 把 SHA-1 在 `hashes` 中的证书导出到临时文件，`security remove-trusted-cert` 只接受证书文件
+async fn export_certs(cert_name: &str, hashes: &[String]) -> Vec<PathBuf> {
     let p = tokio::process::Command::new("security")
-        .arg("verify-cert")
+        .arg("find-certificate")
+        .arg("-a")
+        .arg("-Z")
+        .arg("-p")
         .arg("-c")
-        .arg(cer_path.as_path())
+        .arg(cert_name)
         .output()
         .await
-        .expect("无法检查证书是否已信任");
-
-    p.status.success()
-}
+        .expect("无法导出证书");
 
-pub async fn install_cert() {
-    let config_path = crate::get_config_dir();
-    let cer_path = config_path.as_path().join("ca.cer");
+    if !p.status.success() {
+        return Vec::new();
+    }
 
-    let p = tokio::process::Command::new("osascript")
-    .arg("-e")
-    .arg(format!(
-        r#"do shell script "security authorizationdb write com.apple.trust-settings.admin allow ; security add-trusted-cert -d -r trustAsRoot -p ssl -p basic -s \"localhost\" -k /Library/Keychains/System.keychain \"{}\" ; security authorizationdb remove com.apple.trust-settings.admin" with prompt "太鼓成绩提取器需要安装代理证书" with administrator privileges"#,
-        cer_path.as_path().to_string_lossy()
-    ))
-    .output()
-    .await
-    .expect("无法安装证书");
+    let mut paths = Vec::new();
+    let mut current_hash = None;
+    let mut pem = None::<String>;
+    for line in String::from_utf8_lossy(&p.stdout).lines() {
+        let line = line.trim();
+        if let Some(hash) = line.strip_prefix("SHA-1 hash:") {
+            current_hash = Some(hash.trim().to_string());
+            continue;
+        }
+        if line.starts_with("-----BEGIN CERTIFICATE-----") {
+            pem = Some(String::new());
+        }
+        let Some(data) = &mut pem else {
+            continue;
+        };
+        data.push_str(line);
+        data.push('\n');
+        if !line.starts_with("-----END CERTIFICATE-----") {
+            continue;
+        }
 
-    if !p.status.success() {
-        panic!("证书安装失败");
+        let data = pem.take().unwrap_or_default();
+        if let Some(hash) = current_hash.take().filter(|hash| hashes.contains(hash)) {
+            let path = std::env::temp_dir().join(format!("taiko-score-getter-{hash}.cer"));
+            match tokio::fs::write(&path, data).await {
+                Ok(()) => paths.push(path),
+                Err(err) => tracing::warn!("无法导出证书 {}：{}", hash, err),
+            }
+        }
     }
+    paths
 }
 
 #[derive(Debug, Default)]
@@ -56,6 +180,9 @@ struct ProxyEntry {
     proxy_secure_host: String,
     proxy_secure_port: u16,
     proxy_secure_state: bool,
+
+    auto_proxy_url: String,
+    auto_proxy_state: bool,
 }
 
 #[derive(Debug)]
@@ -125,6 +252,26 @@ impl ProxyConfigs {
                     }
                 }
 
+                let auto_proxy_state = tokio::process::Command::new("networksetup")
+                    .arg("-getautoproxyurl")
+                    .arg(device_name)
+                    .output()
+                    .await
+                    .expect("无法获取自动代理状态");
+                let auto_proxy_state = String::from_utf8_lossy(&auto_proxy_state.stdout);
+
+                for line in auto_proxy_state.lines() {
+                    if line == "Enabled: Yes" {
+                        entry.auto_proxy_state = true;
+                    } else if line == "Enabled: No" {
+                        entry.auto_proxy_state = false;
+                    } else if let Some(url) = line.strip_prefix("URL: ")
+                        && url != "(null)"
+                    {
+                        entry.auto_proxy_url = url.to_string();
+                    }
+                }
+
                 tracing::debug!("发现代理配置 {:?}", entry);
 
                 entries.push(entry);
@@ -133,8 +280,10 @@ impl ProxyConfigs {
 
         Self { entries }
     }
+}
 
-    pub async fn recover(&self) {
+impl SystemProxy for ProxyConfigs {
+    async fn recover(&self) {
         for entry in &self.entries {
             tracing::debug!("正在还原配置 {:?}", entry);
 
@@ -175,11 +324,28 @@ impl ProxyConfigs {
                 .output()
                 .await
                 .expect("无法恢复安全代理状态");
+
+            if !entry.auto_proxy_url.is_empty() {
+                tokio::process::Command::new("networksetup")
+                    .arg("-setautoproxyurl")
+                    .arg(&entry.device)
+                    .arg(&entry.auto_proxy_url)
+                    .output()
+                    .await
+                    .expect("无法恢复自动代理");
+            }
+
+            tokio::process::Command::new("networksetup")
+                .arg("-setautoproxystate")
+                .arg(&entry.device)
+                .arg(if entry.auto_proxy_state { "on" } else { "off" })
+                .output()
+                .await
+                .expect("无法恢复自动代理状态");
         }
     }
 
-    pub async fn set_proxy(&self, proxy_host: impl AsRef<str>, proxy_port: u16) {
-        let proxy_host = proxy_host.as_ref();
+    async fn set_proxy(&self, proxy_host: &str, proxy_port: u16) {
         for entry in &self.entries {
             tokio::process::Command::new("networksetup")
                 .arg("-setwebproxy")
@@ -225,4 +391,91 @@ impl ProxyConfigs {
                 .expect("无法启用安全代理");
         }
     }
+
+    async fn set_auto_config(&self, pac_url: &str) {
+        for entry in &self.entries {
+            tokio::process::Command::new("networksetup")
+                .arg("-setautoproxyurl")
+                .arg(&entry.device)
+                .arg(pac_url)
+                .output()
+                .await
+                .expect("无法设置自动代理");
+
+            tokio::process::Command::new("networksetup")
+                .arg("-setautoproxystate")
+                .arg(&entry.device)
+                .arg("on")
+                .output()
+                .await
+                .expect("无法启用自动代理");
+        }
+    }
+
+    async fn remove_leftover_proxy(&self, proxy_host: &str, proxy_port: u16) -> bool {
+        let mut restored = false;
+        let pac_prefix = format!("http://{proxy_host}:{proxy_port}/");
+
+        for entry in &self.entries {
+            if entry.proxy_state && entry.proxy_host == proxy_host && entry.proxy_port == proxy_port
+            {
+                tokio::process::Command::new("networksetup")
+                    .arg("-setwebproxystate")
+                    .arg(&entry.device)
+                    .arg("off")
+                    .output()
+                    .await
+                    .expect("无法关闭代理");
+                restored = true;
+            }
+
+            if entry.proxy_secure_state
+                && entry.proxy_secure_host == proxy_host
+                && entry.proxy_secure_port == proxy_port
+            {
+                tokio::process::Command::new("networksetup")
+                    .arg("-setsecurewebproxystate")
+                    .arg(&entry.device)
+                    .arg("off")
+                    .output()
+                    .await
+                    .expect("无法关闭安全代理");
+                restored = true;
+            }
+
+            if entry.auto_proxy_state && entry.auto_proxy_url.starts_with(&pac_prefix) {
+                tokio::process::Command::new("networksetup")
+                    .arg("-setautoproxystate")
+                    .arg(&entry.device)
+                    .arg("off")
+                    .output()
+                    .await
+                    .expect("无法关闭自动代理");
+                restored = true;
+            }
+        }
+
+        restored
+    }
+
+    fn upstream_proxy(&self) -> Option<UpstreamProxy> {
+        // HTTPS 代理优先，成绩相关的请求都是 HTTPS 请求
+        let secure = self.entries.iter().find_map(|entry| {
+            (entry.proxy_secure_state && !entry.proxy_secure_host.is_empty()).then(|| {
+                UpstreamProxy::Http {
+                    host: entry.proxy_secure_host.clone(),
+                    port: entry.proxy_secure_port,
+                }
+            })
+        });
+
+        secure.or_else(|| {
+            self.entries.iter().find_map(|entry| {
+                (entry.proxy_state && !entry.proxy_host.is_empty()).then(|| UpstreamProxy::Http {
+                    host: entry.proxy_host.clone(),
+                    port: entry.proxy_port,
+                })
+            })
+        })
+    }
 }
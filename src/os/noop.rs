@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use super::{CertStore, SystemProxy};
+use crate::upstream::UpstreamProxy;
+
+/// 不会修改系统证书库的空实现，用于暂不支持的平台或者试运行
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopCertStore;
+
+impl CertStore for NoopCertStore {
+    async fn is_cert_installed(&self, _cert_name: &str) -> bool {
+        true
+    }
+
+    async fn is_cert_trusted(&self, _cert_name: &str, _cer_path: &Path) -> bool {
+        true
+    }
+
+    async fn install_cert(&self, _cert_name: &str, cer_path: &Path) {
+        tracing::info!(
+            "跳过证书安装，请手动信任证书 {}",
+            cer_path.to_string_lossy()
+        );
+    }
+
+    async fn uninstall_cert(&self, _cert_name: &str, _cer_path: &Path) -> bool {
+        false
+    }
+}
+
+/// 不会修改系统代理的空实现，需要用户自行配置代理
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopProxy;
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+impl NoopProxy {
+    pub async fn new() -> Self {
+        Self
+    }
+}
+
+impl SystemProxy for NoopProxy {
+    async fn set_proxy(&self, proxy_host: &str, proxy_port: u16) {
+        tracing::warn!(
+            "不会自动设置系统代理，请手动将代理设置为 {}:{}",
+            proxy_host,
+            proxy_port
+        );
+    }
+
+    async fn set_auto_config(&self, pac_url: &str) {
+        tracing::warn!(
+            "不会自动设置系统代理，请手动将自动代理配置地址设置为 {}",
+            pac_url
+        );
+    }
+
+    async fn recover(&self) {}
+
+    async fn remove_leftover_proxy(&self, _proxy_host: &str, _proxy_port: u16) -> bool {
+        false
+    }
+
+    fn upstream_proxy(&self) -> Option<UpstreamProxy> {
+        None
+    }
+}
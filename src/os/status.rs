@@ -0,0 +1,169 @@
+use std::fmt;
+
+use hudsucker::rcgen::{
+    CertificateParams, GeneralSubtree, KeyPair, PKCS_RSA_SHA256, PKCS_RSA_SHA384, PKCS_RSA_SHA512,
+};
+use time::OffsetDateTime;
+
+use super::{CertStore, PASSPHRASE_ENV};
+
+/// 证书到期前多少天开始视为即将过期，并在启动时自动更换
+const NEAR_EXPIRY_DAYS: i64 = 30;
+/// RSA 公钥（PKCS#1 DER 编码）的最短长度，约等于 2048 位
+const MIN_RSA_PUBLIC_KEY_LEN: usize = 270;
+
+/// 本地证书文件或系统证书库中存在的问题
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaProblem {
+    Missing,
+    InvalidCert,
+    KeyLocked,
+    KeyMismatch,
+    WeakKey,
+    Expired,
+    ExpiringSoon {
+        days_left: i64,
+    },
+    ConstraintsMismatch,
+    NotInstalled,
+    NotTrusted,
+    /// 无法写入或替换证书文件
+    FileError(String),
+}
+
+impl CaProblem {
+    /// 是否需要重新生成证书才能解决
+    pub fn needs_rotation(&self) -> bool {
+        !matches!(
+            self,
+            Self::KeyLocked | Self::NotInstalled | Self::NotTrusted | Self::FileError(_)
+        )
+    }
+}
+
+impl fmt::Display for CaProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "证书或私钥文件不存在"),
+            Self::InvalidCert => write!(f, "证书文件无法解析"),
+            Self::KeyLocked => write!(
+                f,
+                "私钥文件已加密，请通过环境变量 {PASSPHRASE_ENV} 提供正确的口令"
+            ),
+            Self::KeyMismatch => write!(f, "私钥文件无法解析或与证书不匹配"),
+            Self::WeakKey => write!(f, "私钥强度不足"),
+            Self::Expired => write!(f, "证书已过期"),
+            Self::ExpiringSoon { days_left } => write!(f, "证书将在 {days_left} 天后过期"),
+            Self::ConstraintsMismatch => {
+                write!(f, "证书允许签发的域名与当前需要拦截的域名不一致")
+            }
+            Self::NotInstalled => write!(f, "证书尚未安装到系统证书库"),
+            Self::NotTrusted => write!(f, "证书已安装但尚未被信任"),
+            Self::FileError(err) => write!(f, "无法保存证书文件：{err}"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CaStatus {
+    pub cert_name: Option<String>,
+    pub not_after: Option<OffsetDateTime>,
+    pub problems: Vec<CaProblem>,
+}
+
+impl CaStatus {
+    pub fn needs_rotation(&self) -> bool {
+        self.problems.iter().any(CaProblem::needs_rotation)
+    }
+}
+
+fn cert_public_key(cer_data: &str) -> Option<Vec<u8>> {
+    let (_, pem) = x509_parser::pem::parse_x509_pem(cer_data.as_bytes()).ok()?;
+    let cert = pem.parse_x509().ok()?;
+    Some(cert.public_key().raw.to_vec())
+}
+
+fn is_strong_key(key_pair: &KeyPair) -> bool {
+    let is_rsa = [&PKCS_RSA_SHA256, &PKCS_RSA_SHA384, &PKCS_RSA_SHA512]
+        .iter()
+        .any(|alg| key_pair.algorithm() == *alg);
+
+    !is_rsa || key_pair.public_key_raw().len() >= MIN_RSA_PUBLIC_KEY_LEN
+}
+
+fn constraints_match(params: &CertificateParams, permitted_hosts: &[&str]) -> bool {
+    let Some(constraints) = &params.name_constraints else {
+        return false;
+    };
+
+    let mut constrained: Vec<&str> = constraints
+        .permitted_subtrees
+        .iter()
+        .filter_map(|subtree| match subtree {
+            GeneralSubtree::DnsName(name) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut permitted_hosts = permitted_hosts.to_vec();
+    constrained.sort_unstable();
+    permitted_hosts.sort_unstable();
+
+    constrained == permitted_hosts && constraints.excluded_subtrees.is_empty()
+}
+
+/// 检查配置目录中的证书文件，以及系统证书库中是否安装了对应的证书
+pub async fn check_ca(
+    config_path: &std::path::Path,
+    permitted_hosts: &[&str],
+    cert_store: &impl CertStore,
+) -> CaStatus {
+    let cer_path = config_path.join("ca.cer");
+    let key_path = config_path.join("ca.key");
+
+    let mut status = CaStatus::default();
+
+    let Ok(cer_data) = tokio::fs::read_to_string(&cer_path).await else {
+        status.problems.push(CaProblem::Missing);
+        return status;
+    };
+    let Ok(params) = CertificateParams::from_ca_cert_pem(&cer_data) else {
+        status.problems.push(CaProblem::InvalidCert);
+        return status;
+    };
+
+    status.cert_name = super::cert_name(&params);
+    status.not_after = Some(params.not_after);
+
+    match super::read_key_file(&key_path).await {
+        Ok(key_pair) => {
+            if cert_public_key(&cer_data) != Some(key_pair.public_key_der()) {
+                status.problems.push(CaProblem::KeyMismatch);
+            }
+            if !is_strong_key(&key_pair) {
+                status.problems.push(CaProblem::WeakKey);
+            }
+        }
+        Err(problem) => status.problems.push(problem),
+    }
+
+    let days_left = (params.not_after - OffsetDateTime::now_utc()).whole_days();
+    if params.not_after <= OffsetDateTime::now_utc() {
+        status.problems.push(CaProblem::Expired);
+    } else if days_left < NEAR_EXPIRY_DAYS {
+        status.problems.push(CaProblem::ExpiringSoon { days_left });
+    }
+
+    if !constraints_match(&params, permitted_hosts) {
+        status.problems.push(CaProblem::ConstraintsMismatch);
+    }
+
+    if let Some(cert_name) = &status.cert_name {
+        if !cert_store.is_cert_installed(cert_name).await {
+            status.problems.push(CaProblem::NotInstalled);
+        } else if !cert_store.is_cert_trusted(cert_name, &cer_path).await {
+            status.problems.push(CaProblem::NotTrusted);
+        }
+    }
+
+    status
+}
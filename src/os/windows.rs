@@ -1,52 +1,193 @@
+use std::{io, path::Path};
+
 use sysproxy::Sysproxy;
 
-pub async fn is_cert_installed() -> bool {
-    let p = tokio::process::Command::new("certutil")
-        .arg("-verifystore")
-        .arg("root")
-        .arg("Taiko Score Getter Certificate")
+use super::{CertStore, SystemProxy};
+use crate::upstream::UpstreamProxy;
+
+unsafe extern "system" {
+    fn AttachConsole(process_id: u32) -> i32;
+}
+
+/// 发布版本使用图形界面子系统，没有控制台，命令行子命令需要连接到启动程序的控制台才能输出
+///
+/// 不是从控制台启动时什么都不做
+pub fn attach_parent_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    // SAFETY: AttachConsole 没有指针参数，失败时只返回 0
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+/// 通过 `certutil` 管理的系统根证书库
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemCertStore;
+
+impl CertStore for SystemCertStore {
+    async fn is_cert_installed(&self, cert_name: &str) -> bool {
+        let p = tokio::process::Command::new("certutil")
+            .arg("-verifystore")
+            .arg("root")
+            .arg(cert_name)
+            .creation_flags(0x08000000)
+            .output()
+            .await
+            .expect("无法检查证书是否已安装");
+
+        p.status.success()
+    }
+
+    async fn is_cert_trusted(&self, cert_name: &str, _cer_path: &Path) -> bool {
+        // TODO: 证书信任检查
+        let p = tokio::process::Command::new("certutil")
+            .arg("-store")
+            .arg("root")
+            .arg(cert_name)
+            .creation_flags(0x08000000)
+            .output()
+            .await
+            .expect("无法检查证书是否已信任");
+
+        p.status.success()
+    }
+
+    async fn install_cert(&self, _cert_name: &str, cer_path: &Path) {
+        // certutil.exe -addstore root mitmproxy-ca-cert.cer
+        tokio::process::Command::new("certutil")
+            .arg("-addstore")
+            .arg("root")
+            .arg(cer_path)
+            .creation_flags(0x08000000)
+            .status()
+            .await
+            .expect("无法安装证书");
+
+        tracing::info!("证书已安装");
+    }
+
+    async fn uninstall_cert(&self, cert_name: &str, _cer_path: &Path) -> bool {
+        let mut removed = false;
+
+        // 多次运行可能会安装了多张同名证书，需要全部移除
+        while self.is_cert_installed(cert_name).await {
+            let p = tokio::process::Command::new("certutil")
+                .arg("-delstore")
+                .arg("root")
+                .arg(cert_name)
+                .creation_flags(0x08000000)
+                .output()
+                .await
+                .expect("无法卸载证书");
+
+            if !p.status.success() {
+                tracing::warn!("证书卸载失败：{}", String::from_utf8_lossy(&p.stdout));
+                break;
+            }
+
+            removed = true;
+        }
+
+        removed
+    }
+}
+
+/// 移除私钥文件继承的权限，仅允许管理员和系统账户访问
+pub async fn protect_key_file(key_path: &Path) -> io::Result<()> {
+    let p = tokio::process::Command::new("icacls")
+        .arg(key_path)
+        .arg("/inheritance:r")
+        .arg("/grant:r")
+        .arg("*S-1-5-32-544:F")
+        .arg("*S-1-5-18:F")
         .creation_flags(0x08000000)
         .output()
-        .await
-        .expect("无法检查证书是否已安装");
+        .await?;
 
-    p.status.success()
+    if !p.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&p.stdout).into_owned(),
+        ));
+    }
+    Ok(())
 }
 
-pub async fn is_cert_trusted() -> bool {
-    // TODO: 证书信任检查
-    let p = tokio::process::Command::new("certutil")
-        .arg("-store")
-        .arg("root")
-        .arg("Taiko Score Getter Certificate")
+/// 私钥文件是否还有继承的权限，即没有经过 [`protect_key_file`] 处理，只读取不修改
+pub async fn is_key_file_inherited(key_path: &Path) -> io::Result<bool> {
+    let p = tokio::process::Command::new("icacls")
+        .arg(key_path)
         .creation_flags(0x08000000)
         .output()
-        .await
-        .expect("无法检查证书是否已信任");
+        .await?;
+
+    if !p.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&p.stdout).into_owned(),
+        ));
+    }
+    // 继承的权限项带有 (I) 标记
+    Ok(String::from_utf8_lossy(&p.stdout).contains("(I)"))
+}
 
-    p.status.success()
+#[derive(Debug)]
+pub struct ProxyConfigs {
+    last_proxy: Sysproxy,
+    last_auto_config: Option<String>,
 }
 
-pub async fn install_cert() {
-    let config_path = crate::get_config_dir();
-    let cer_path = config_path.as_path().join("ca.cer");
+const INTERNET_SETTINGS_KEY: &str =
+    r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings";
 
-    // certutil.exe -addstore root mitmproxy-ca-cert.cer
-    tokio::process::Command::new("certutil")
-        .arg("-addstore")
-        .arg("root")
-        .arg(cer_path)
+async fn get_auto_config_url() -> Option<String> {
+    let p = tokio::process::Command::new("reg")
+        .arg("query")
+        .arg(INTERNET_SETTINGS_KEY)
+        .arg("/v")
+        .arg("AutoConfigURL")
         .creation_flags(0x08000000)
-        .status()
+        .output()
         .await
-        .expect("无法安装证书");
+        .expect("无法读取自动代理配置");
+
+    if !p.status.success() {
+        return None;
+    }
 
-    tracing::info!("证书已安装");
+    String::from_utf8_lossy(&p.stdout)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("AutoConfigURL"))
+        .and_then(|value| value.trim().strip_prefix("REG_SZ"))
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
 }
 
-#[derive(Debug)]
-pub struct ProxyConfigs {
-    last_proxy: Sysproxy,
+/// 修改注册表中的自动代理配置地址，传入 `None` 时删除
+async fn set_auto_config_url(url: Option<&str>) {
+    let mut command = tokio::process::Command::new("reg");
+    match url {
+        Some(url) => command
+            .arg("add")
+            .arg(INTERNET_SETTINGS_KEY)
+            .arg("/v")
+            .arg("AutoConfigURL")
+            .arg("/t")
+            .arg("REG_SZ")
+            .arg("/d")
+            .arg(url)
+            .arg("/f"),
+        None => command
+            .arg("delete")
+            .arg(INTERNET_SETTINGS_KEY)
+            .arg("/v")
+            .arg("AutoConfigURL")
+            .arg("/f"),
+    };
+
+    command
+        .creation_flags(0x08000000)
+        .output()
+        .await
+        .expect("无法设置自动代理配置");
 }
 
 impl ProxyConfigs {
@@ -58,21 +199,63 @@ impl ProxyConfigs {
                 port: 0,
                 bypass: "".into(),
             }),
+            last_auto_config: get_auto_config_url().await,
         }
     }
+}
 
-    pub async fn recover(&self) {
+impl SystemProxy for ProxyConfigs {
+    async fn recover(&self) {
+        set_auto_config_url(self.last_auto_config.as_deref()).await;
+        // 重新写入代理配置的同时会通知系统刷新设置
         self.last_proxy
             .set_system_proxy()
             .expect("无法还原系统代理配置");
     }
 
-    pub async fn set_proxy(&self, proxy_host: impl AsRef<str>, proxy_port: u16) {
+    async fn set_proxy(&self, proxy_host: &str, proxy_port: u16) {
         let mut proxy = self.last_proxy.clone();
         proxy.enable = true;
-        proxy.host = proxy_host.as_ref().to_string();
+        proxy.host = proxy_host.to_string();
         proxy.port = proxy_port;
 
         proxy.set_system_proxy().expect("无法设置系统代理配置");
     }
+
+    async fn set_auto_config(&self, pac_url: &str) {
+        set_auto_config_url(Some(pac_url)).await;
+        // 手动代理配置保持不变，重新写入只是为了通知系统刷新设置
+        self.last_proxy
+            .set_system_proxy()
+            .expect("无法设置系统代理配置");
+    }
+
+    async fn remove_leftover_proxy(&self, proxy_host: &str, proxy_port: u16) -> bool {
+        let leftover_auto_config = self
+            .last_auto_config
+            .as_ref()
+            .is_some_and(|url| url.starts_with(&format!("http://{proxy_host}:{proxy_port}/")));
+        if leftover_auto_config {
+            set_auto_config_url(None).await;
+        }
+
+        let mut proxy = self.last_proxy.clone();
+        let leftover_proxy = proxy.enable && proxy.host == proxy_host && proxy.port == proxy_port;
+        if leftover_proxy {
+            proxy.enable = false;
+        }
+
+        if leftover_proxy || leftover_auto_config {
+            proxy.set_system_proxy().expect("无法还原系统代理配置");
+        }
+
+        leftover_proxy || leftover_auto_config
+    }
+
+    fn upstream_proxy(&self) -> Option<UpstreamProxy> {
+        (self.last_proxy.enable && !self.last_proxy.host.is_empty()).then(|| UpstreamProxy::Http {
+            host: self.last_proxy.host.clone(),
+            port: self.last_proxy.port,
+        })
+    }
 }
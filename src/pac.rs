@@ -0,0 +1,43 @@
+//! 自动代理配置（PAC）模式，只有成绩相关的域名会经过本程序，其它请求不受影响
+
+use std::net::SocketAddr;
+
+use crate::upstream::UpstreamProxy;
+
+/// 代理服务器上提供 PAC 脚本的路径
+pub const PAC_PATH: &str = "/proxy.pac";
+pub const PAC_CONTENT_TYPE: &str = "application/x-ns-proxy-autoconfig";
+
+pub fn pac_url(listen_addr: SocketAddr) -> String {
+    format!("http://{listen_addr}{PAC_PATH}")
+}
+
+/// 生成 PAC 脚本，指定的域名交给本程序处理，其它域名交给原有的上游代理或者直接连接
+pub fn generate_pac(
+    hosts: &[&str],
+    listen_addr: SocketAddr,
+    upstream_proxy: Option<&UpstreamProxy>,
+) -> String {
+    let conditions = hosts
+        .iter()
+        .map(|host| format!("host === \"{}\"", host.to_ascii_lowercase()))
+        .collect::<Vec<_>>()
+        .join(" || ");
+
+    let fallback = match upstream_proxy {
+        Some(UpstreamProxy::Http { host, port }) => format!("PROXY {host}:{port}; DIRECT"),
+        Some(UpstreamProxy::Socks5 { host, port }) => format!("SOCKS5 {host}:{port}; DIRECT"),
+        None => "DIRECT".to_string(),
+    };
+
+    format!(
+        r#"function FindProxyForURL(url, host) {{
+    host = host.toLowerCase();
+    if ({conditions}) {{
+        return "PROXY {listen_addr}";
+    }}
+    return "{fallback}";
+}}
+"#
+    )
+}
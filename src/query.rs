@@ -0,0 +1,313 @@
+//! 在捕获记录中按条件筛选和排序成绩，供 `scores query` 命令使用
+
+use std::{cmp::Ordering, str::FromStr};
+
+use serde_json::Value;
+
+use crate::{
+    catalog::Catalog,
+    goals::{Crown, Rank},
+    history::{self, CaptureRecord},
+    songs_score::{self, ScoreInfo},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+    Csv,
+}
+
+/// 闭区间，写法为 `9`、`8..10`、`9..` 或者 `..8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl Bounds {
+    fn contains(&self, value: i64) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+}
+
+impl FromStr for Bounds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |x: &str| -> Result<Option<i64>, String> {
+            match x.trim() {
+                "" => Ok(None),
+                x => x.parse().map(Some).map_err(|_| format!("无效的数字 {x}")),
+            }
+        };
+        match s.split_once("..") {
+            Some((min, max)) => Ok(Self {
+                min: parse(min)?,
+                max: parse(max)?,
+            }),
+            None => {
+                let value = parse(s)?;
+                Ok(Self {
+                    min: value,
+                    max: value,
+                })
+            }
+        }
+    }
+}
+
+/// 难度可以写 1 到 5，也可以写名称，例如 魔王
+fn parse_level(s: &str) -> Result<i64, String> {
+    let s = s.trim();
+    match s.parse::<i64>() {
+        Ok(level) if (1..=5).contains(&level) => Ok(level),
+        Ok(level) => Err(format!("难度需要在 1 到 5 之间：{level}")),
+        Err(_) => (1..=5)
+            .find(|level| songs_score::difficulty_name(*level) == s)
+            .ok_or_else(|| format!("未知的难度 {s}")),
+    }
+}
+
+/// 检查日期（YYYY-MM-DD）是否有效，并统一为补零的格式，方便和成绩中的时间比较
+pub fn parse_date(s: &str) -> Result<String, String> {
+    history::parse_timestamp(&format!("{} 00:00:00", s.trim()))
+        .map(|timestamp| history::format_timestamp(timestamp)[..10].to_string())
+        .ok_or_else(|| format!("无效的日期 {s}，需要使用 YYYY-MM-DD 格式"))
+}
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct ScoreQuery {
+    /// 使用指定日期（YYYY-MM-DD）当天或之前的最后一次捕获，默认使用最近一次捕获
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    pub at: Option<String>,
+    /// 难度，可以写 1 到 5 或者名称，可以指定多次
+    #[arg(long, value_parser = parse_level)]
+    pub level: Vec<i64>,
+    /// 星级，例如 9、8..10 或 9..
+    #[arg(long, value_name = "RANGE")]
+    pub stars: Option<Bounds>,
+    /// 歌曲分类，包含指定文字即可
+    #[arg(long)]
+    pub family: Option<String>,
+    /// 至少达到的王冠
+    #[arg(long)]
+    pub crown: Option<Crown>,
+    /// 至少达到的评价，可以是编号（2 到 8）或者名称，例如 金雅
+    #[arg(long)]
+    pub rank: Option<Rank>,
+    /// 分数范围，例如 900000.. 或 800000..950000
+    #[arg(long, value_name = "RANGE")]
+    pub score: Option<Bounds>,
+    /// 中文或日文曲名包含的文字，不区分大小写
+    #[arg(long)]
+    pub title: Option<String>,
+    /// 只包括最近游玩日期（update_datetime）在指定日期当天或之后的成绩
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    pub since: Option<String>,
+    /// 只包括最近游玩日期在指定日期当天或之前的成绩
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    pub until: Option<String>,
+    /// 排序使用的字段，可以是 ScoreInfo 或 SongDetail 中的任意字段，以及 stars 和 crown
+    #[arg(long, value_name = "FIELD")]
+    pub sort: Option<String>,
+    /// 从大到小排序
+    #[arg(long)]
+    pub desc: bool,
+    /// 最多输出的成绩数量
+    #[arg(long)]
+    pub limit: Option<usize>,
+    /// 输出格式
+    #[arg(long, value_enum, default_value_t)]
+    pub format: OutputFormat,
+}
+
+impl ScoreQuery {
+    /// 按 `--at` 选择捕获记录，`records` 需要按捕获时间从早到晚排列
+    pub fn select<'a>(&self, records: &'a [CaptureRecord]) -> Option<&'a CaptureRecord> {
+        match &self.at {
+            Some(date) => {
+                let end = history::parse_timestamp(&format!("{date} 23:59:59"))?;
+                records
+                    .iter()
+                    .rev()
+                    .find(|record| record.captured_at <= end)
+            }
+            None => records.last(),
+        }
+    }
+
+    fn matches(&self, item: &ScoreInfo, catalog: Option<&Catalog>) -> bool {
+        let detail = Catalog::detail_for(catalog, item);
+        let title = self.title.as_ref().map(|x| x.to_lowercase());
+        let date = item.update_datetime.get(..10).unwrap_or_default();
+
+        (self.level.is_empty() || self.level.contains(&item.level))
+            && self.stars.is_none_or(|stars| {
+                detail
+                    .stars(item.level)
+                    .is_some_and(|x| stars.contains(i64::from(x)))
+            })
+            && self
+                .family
+                .as_ref()
+                .is_none_or(|x| detail.family.to_lowercase().contains(&x.to_lowercase()))
+            && self.crown.is_none_or(|crown| crown.reached(item))
+            && self.rank.is_none_or(|rank| rank.reached(item))
+            && self
+                .score
+                .is_none_or(|score| score.contains(item.high_score))
+            && title.is_none_or(|title| {
+                [&detail.song_name, &detail.song_name_jp]
+                    .iter()
+                    .any(|name| name.to_lowercase().contains(&title))
+            })
+            && self.since.as_deref().is_none_or(|since| date >= since)
+            && self.until.as_deref().is_none_or(|until| date <= until)
+    }
+
+    /// 筛选并排序，排序字段不存在时返回错误
+    pub fn apply<'a>(
+        &self,
+        record: &'a CaptureRecord,
+        catalog: Option<&Catalog>,
+    ) -> Result<Vec<&'a ScoreInfo>, String> {
+        let mut items = record
+            .scores
+            .score_info
+            .iter()
+            .filter(|item| self.matches(item, catalog))
+            .collect::<Vec<_>>();
+
+        if let Some(field) = &self.sort {
+            if ScoreInfo::default().field(field).is_none() {
+                let fields = songs_score::field_names().join("、");
+                return Err(format!("未知的排序字段 {field}，可用的字段：{fields}"));
+            }
+            let mut keyed = items
+                .into_iter()
+                .map(|item| {
+                    let detail = Catalog::detail_for(catalog, item);
+                    (item.field_with(detail, field).unwrap_or_default(), item)
+                })
+                .collect::<Vec<_>>();
+            keyed.sort_by(|(a, _), (b, _)| {
+                let ordering = compare_values(a, b);
+                if self.desc {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+            items = keyed.into_iter().map(|(_, item)| item).collect();
+        }
+
+        if let Some(limit) = self.limit {
+            items.truncate(limit);
+        }
+        Ok(items)
+    }
+}
+
+/// 数字按大小比较，字符串按字典序比较，空值排在最前面
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            _ => 4,
+        }
+    }
+
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a
+            .as_f64()
+            .unwrap_or_default()
+            .total_cmp(&b.as_f64().unwrap_or_default()),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// CSV 的列，包括恢复捕获记录需要的所有字段
+pub const CSV_HEADER: &[&str] = &[
+    "userid",
+    "captured_at",
+    "song_no",
+    "level",
+    "high_score",
+    "best_score_rank",
+    "good_cnt",
+    "ok_cnt",
+    "ng_cnt",
+    "pound_cnt",
+    "combo_cnt",
+    "stage_cnt",
+    "clear_cnt",
+    "full_combo_cnt",
+    "dondaful_combo_cnt",
+    "highscore_datetime",
+    "update_datetime",
+    "song_name",
+    "song_name_jp",
+    "family",
+    "stars",
+    "partial",
+];
+
+/// 导出为 CSV，筛选后只导出部分成绩时每一行的 `partial` 列为 1
+pub fn to_csv(record: &CaptureRecord, items: &[&ScoreInfo], catalog: Option<&Catalog>) -> String {
+    let partial = if items.len() < record.scores.score_info.len() {
+        "1"
+    } else {
+        ""
+    };
+    let mut output = CSV_HEADER.join(",");
+    output.push('\n');
+    for item in items {
+        let detail = Catalog::detail_for(catalog, item);
+        let row = [
+            record.scores.userid.clone(),
+            record.captured_at.to_string(),
+            item.song_no.to_string(),
+            item.level.to_string(),
+            item.high_score.to_string(),
+            item.best_score_rank.to_string(),
+            item.good_cnt.to_string(),
+            item.ok_cnt.to_string(),
+            item.ng_cnt.to_string(),
+            item.pound_cnt.to_string(),
+            item.combo_cnt.to_string(),
+            item.stage_cnt.to_string(),
+            item.clear_cnt.to_string(),
+            item.full_combo_cnt.to_string(),
+            item.dondaful_combo_cnt.to_string(),
+            item.highscore_datetime.clone(),
+            item.update_datetime.clone(),
+            detail.song_name.clone(),
+            detail.song_name_jp.clone(),
+            detail.family.clone(),
+            detail
+                .stars(item.level)
+                .map(|x| x.to_string())
+                .unwrap_or_default(),
+            partial.to_string(),
+        ];
+        let row = row.iter().map(|x| csv_field(x)).collect::<Vec<_>>();
+        output.push_str(&row.join(","));
+        output.push('\n');
+    }
+    output
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,144 @@
+//! 玩家评分，取单曲评分最高的 N 个谱面计算，不同版本的公式可以同时存在
+//!
+//! 公式一旦发布就不应该再修改，需要调整时增加一个新版本，避免新旧结果混在一起比较
+
+use serde::Serialize;
+
+use crate::{
+    catalog::Catalog,
+    songs_score::{self, ScoreInfo, SongDetail},
+};
+
+pub trait RatingFormula: Sync {
+    /// 公式的版本号，会随结果一起输出
+    fn version(&self) -> &'static str;
+    /// 计入综合评分的谱面数量
+    fn best_n(&self) -> usize;
+    /// 单个谱面的评分，不计入评分的谱面返回 `None`，歌曲信息以 `detail` 为准
+    fn song_rating(&self, item: &ScoreInfo, detail: &SongDetail) -> Option<f64>;
+}
+
+/// 第一版公式：星级 × 10 × (0.6 × 准确率 + 0.4 × 分数系数) × 王冠系数，取最好的 30 个谱面的平均值
+///
+/// - 准确率为 (良 + 可 / 2) / 总判定数
+/// - 分数系数在 50 万分时为 0，100 万分及以上为 1，中间线性增长
+/// - 王冠系数：通关 1.0，全连 1.05，全良 1.1，未通关的谱面不计入
+#[derive(Debug, Clone, Copy)]
+pub struct V1;
+
+impl RatingFormula for V1 {
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn best_n(&self) -> usize {
+        30
+    }
+
+    fn song_rating(&self, item: &ScoreInfo, detail: &SongDetail) -> Option<f64> {
+        let stars = detail.stars(item.level)?;
+
+        let crown = if item.dondaful_combo_cnt > 0 {
+            1.1
+        } else if item.full_combo_cnt > 0 {
+            1.05
+        } else if item.clear_cnt > 0 {
+            1.0
+        } else {
+            return None;
+        };
+
+        let notes = item.good_cnt + item.ok_cnt + item.ng_cnt;
+        if notes <= 0 {
+            return None;
+        }
+        let accuracy = (item.good_cnt as f64 + item.ok_cnt as f64 / 2.0) / notes as f64;
+        let score = ((item.high_score as f64 - 500_000.0) / 500_000.0).clamp(0.0, 1.0);
+
+        Some(f64::from(stars) * 10.0 * (0.6 * accuracy + 0.4 * score) * crown)
+    }
+}
+
+/// 所有可用的公式，第一个为默认公式
+pub static FORMULAS: &[&dyn RatingFormula] = &[&V1];
+
+pub fn formula(version: &str) -> Option<&'static dyn RatingFormula> {
+    FORMULAS
+        .iter()
+        .copied()
+        .find(|formula| formula.version().eq_ignore_ascii_case(version))
+}
+
+pub fn default_formula() -> &'static dyn RatingFormula {
+    FORMULAS[0]
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Contribution {
+    pub song_no: i64,
+    pub level: i64,
+    pub stars: Option<u8>,
+    pub song_name: String,
+    pub high_score: i64,
+    pub best_score_rank: i64,
+    pub crown: &'static str,
+    pub rating: f64,
+    /// 是否属于计入综合评分的 N 个谱面
+    pub counted: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RatingReport {
+    pub formula: &'static str,
+    pub best_n: usize,
+    /// 最好的 N 个谱面的评分之和除以 N，谱面不足 N 个时按 0 分计算
+    pub rating: f64,
+    /// 按单曲评分从高到低排列，不计入评分的谱面不会出现
+    pub contributions: Vec<Contribution>,
+}
+
+/// 计算一次捕获的评分，歌曲名称优先使用歌曲目录中的数据
+pub fn rate(
+    formula: &dyn RatingFormula,
+    data: &songs_score::Data,
+    catalog: Option<&Catalog>,
+) -> RatingReport {
+    let mut contributions = data
+        .score_info
+        .iter()
+        .filter_map(|item| {
+            let detail = Catalog::detail_for(catalog, item);
+            let rating = formula.song_rating(item, detail)?;
+            Some(Contribution {
+                song_no: item.song_no,
+                level: item.level,
+                stars: detail.stars(item.level),
+                song_name: detail.song_name.clone(),
+                high_score: item.high_score,
+                best_score_rank: item.best_score_rank,
+                crown: item.crown_name(),
+                rating: round2(rating),
+                counted: false,
+            })
+        })
+        .collect::<Vec<_>>();
+    contributions.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+
+    let best_n = formula.best_n();
+    let mut total = 0.0;
+    for contribution in contributions.iter_mut().take(best_n) {
+        contribution.counted = true;
+        total += contribution.rating;
+    }
+
+    RatingReport {
+        formula: formula.version(),
+        best_n,
+        rating: round2(total / best_n as f64),
+        contributions,
+    }
+}
+
+fn round2(x: f64) -> f64 {
+    (x * 100.0).round() / 100.0
+}
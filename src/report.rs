@@ -0,0 +1,357 @@
+//! 个人进度报告，根据所有捕获记录生成一个 HTML 文件
+//!
+//! 样式、脚本和图表都内联在文件中，不引用任何外部资源，可以直接通过邮件发送
+
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    card::escape_xml,
+    catalog::Catalog,
+    history::{self, CaptureRecord},
+    rating::{self, RatingFormula},
+    songs_score,
+    stats::{CrownCounts, Stats},
+};
+
+/// 最近提升中列出的谱面数量
+const RECENT_LIMIT: usize = 20;
+
+const STYLE: &str = r#"
+body { margin: 0 auto; max-width: 1080px; padding: 24px; font-family: sans-serif; color: #332a22; background: #fbf4e6; }
+h1 { margin: 0; padding: 24px; color: #fff; background: #e84a27; border-radius: 8px; }
+h1 small { display: block; margin-top: 8px; font-size: 14px; font-weight: normal; }
+h2 { margin-top: 32px; border-bottom: 2px solid #e84a27; }
+table { width: 100%; border-collapse: collapse; font-size: 14px; }
+th, td { padding: 6px 8px; text-align: left; border-bottom: 1px solid #e6d9c0; }
+th { background: #f3e8d2; }
+td.number { text-align: right; font-variant-numeric: tabular-nums; }
+.up { color: #2e7d32; }
+.muted { color: #8a7f72; }
+input { width: 100%; box-sizing: border-box; margin-bottom: 8px; padding: 8px; font-size: 16px; }
+svg text { font-size: 12px; fill: #332a22; }
+"#;
+
+const SCRIPT: &str = r##"
+document.getElementById("search").addEventListener("input", function (event) {
+  var query = event.target.value.trim().toLowerCase();
+  document.querySelectorAll("#songs tbody tr").forEach(function (row) {
+    row.hidden = query !== "" && row.dataset.search.indexOf(query) < 0;
+  });
+});
+"##;
+
+/// 生成报告，`records` 需要按捕获时间从早到晚排列，只包括最后一条记录所属玩家的数据
+pub fn render(
+    records: &[CaptureRecord],
+    catalog: Option<&Catalog>,
+    formula: &dyn RatingFormula,
+) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n");
+    html.push_str("<title>太鼓达人成绩报告</title>\n");
+    writeln!(html, "<style>{}</style>\n</head>\n<body>", STYLE).unwrap();
+
+    let Some(latest) = records.last() else {
+        html.push_str("<p>还没有捕获记录</p>\n</body>\n</html>\n");
+        return html;
+    };
+    let records = records
+        .iter()
+        .filter(|record| record.scores.userid == latest.scores.userid)
+        .collect::<Vec<_>>();
+
+    let player = format!("用户 {}", latest.scores.userid);
+    writeln!(
+        html,
+        "<h1>{}<small>用户 ID {}，{} 次捕获，{} 至 {}，评分公式 {}</small></h1>",
+        escape_xml(&player),
+        escape_xml(&latest.scores.userid),
+        records.len(),
+        history::format_timestamp(records[0].captured_at),
+        history::format_timestamp(latest.captured_at),
+        formula.version()
+    )
+    .unwrap();
+
+    progress(&mut html, &records, catalog, formula);
+    crowns(&mut html, &Stats::new(&latest.scores, catalog));
+    let previous = records.len().checked_sub(2).map(|index| records[index]);
+    recent(&mut html, latest, previous, catalog);
+    songs(&mut html, latest, catalog);
+
+    writeln!(html, "<script>{}</script>\n</body>\n</html>", SCRIPT).unwrap();
+    html
+}
+
+/// 魔王和里魔王的王冠数量之和
+fn oni_crowns(stats: &Stats) -> CrownCounts {
+    let mut total = CrownCounts::default();
+    for crowns in stats.by_difficulty.range(4..).map(|(_, crowns)| crowns) {
+        total.played += crowns.played;
+        total.clear += crowns.clear;
+        total.full_combo += crowns.full_combo;
+        total.donderful += crowns.donderful;
+    }
+    total
+}
+
+fn progress(
+    html: &mut String,
+    records: &[&CaptureRecord],
+    catalog: Option<&Catalog>,
+    formula: &dyn RatingFormula,
+) {
+    let ratings = records
+        .iter()
+        .map(|record| rating::rate(formula, &record.scores, catalog).rating)
+        .collect::<Vec<_>>();
+
+    html.push_str("<h2>进度</h2>\n");
+    rating_chart(html, &ratings);
+
+    html.push_str("<table>\n<thead><tr><th>捕获时间</th><th>评分</th><th>魔王通关</th><th>魔王全连</th><th>魔王全良</th><th>良率</th></tr></thead>\n<tbody>\n");
+    for (record, rating) in records.iter().zip(&ratings).rev() {
+        let stats = Stats::new(&record.scores, catalog);
+        let crowns = oni_crowns(&stats);
+        writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"number\">{:.2}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{:.1}%</td></tr>",
+            history::format_timestamp(record.captured_at),
+            rating,
+            crowns.clear,
+            crowns.full_combo,
+            crowns.donderful,
+            stats.accuracy.good_rate * 100.0
+        )
+        .unwrap();
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+/// 评分随捕获次数变化的折线图，横轴按捕获顺序等距排列
+fn rating_chart(html: &mut String, ratings: &[f64]) {
+    const WIDTH: f64 = 720.0;
+    const HEIGHT: f64 = 200.0;
+    const MARGIN: f64 = 32.0;
+
+    let max = ratings.iter().copied().fold(0.0, f64::max).max(1.0);
+    let step = if ratings.len() > 1 {
+        (WIDTH - MARGIN * 2.0) / (ratings.len() - 1) as f64
+    } else {
+        0.0
+    };
+    let points = ratings
+        .iter()
+        .enumerate()
+        .map(|(index, rating)| {
+            let x = MARGIN + index as f64 * step;
+            let y = HEIGHT - MARGIN - rating / max * (HEIGHT - MARGIN * 2.0);
+            (x, y, *rating)
+        })
+        .collect::<Vec<_>>();
+
+    writeln!(
+        html,
+        "<svg viewBox=\"0 0 {WIDTH} {HEIGHT}\" width=\"100%\" role=\"img\" aria-label=\"评分变化\">"
+    )
+    .unwrap();
+    writeln!(
+        html,
+        "<line x1=\"{MARGIN}\" y1=\"{0}\" x2=\"{1}\" y2=\"{0}\" stroke=\"#8a7f72\"/>",
+        HEIGHT - MARGIN,
+        WIDTH - MARGIN
+    )
+    .unwrap();
+    let polyline = points
+        .iter()
+        .map(|(x, y, _)| format!("{x:.1},{y:.1}"))
+        .collect::<Vec<_>>();
+    writeln!(
+        html,
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"#e84a27\" stroke-width=\"2\"/>",
+        polyline.join(" ")
+    )
+    .unwrap();
+    for (x, y, rating) in points {
+        writeln!(
+            html,
+            "<circle cx=\"{x:.1}\" cy=\"{y:.1}\" r=\"4\" fill=\"#e84a27\"/><text x=\"{x:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{rating:.2}</text>",
+            y - 10.0
+        )
+        .unwrap();
+    }
+    html.push_str("</svg>\n");
+}
+
+/// 各难度的王冠数量，从游玩到全良依次叠加的条形图
+fn crowns(html: &mut String, stats: &Stats) {
+    const WIDTH: f64 = 720.0;
+    const LABEL: f64 = 64.0;
+    const ROW: f64 = 32.0;
+
+    let max = stats
+        .by_difficulty
+        .values()
+        .map(|crowns| crowns.played)
+        .max()
+        .unwrap_or_default()
+        .max(1) as f64;
+    let height = ROW * stats.by_difficulty.len().max(1) as f64 + ROW;
+
+    html.push_str("<h2>各难度王冠</h2>\n");
+    writeln!(
+        html,
+        "<svg viewBox=\"0 0 {WIDTH} {height}\" width=\"100%\" role=\"img\" aria-label=\"各难度王冠\">"
+    )
+    .unwrap();
+    for (index, (level, crowns)) in stats.by_difficulty.iter().enumerate() {
+        let y = index as f64 * ROW;
+        writeln!(
+            html,
+            "<text x=\"0\" y=\"{:.1}\">{}</text>",
+            y + 18.0,
+            songs_score::difficulty_name(*level)
+        )
+        .unwrap();
+        let bars = [
+            (crowns.played, "#e6d9c0"),
+            (crowns.clear, "#8a7f72"),
+            (crowns.full_combo, "#d49a00"),
+            (crowns.donderful, "#d94fa0"),
+        ];
+        for (count, color) in bars {
+            writeln!(
+                html,
+                "<rect x=\"{LABEL}\" y=\"{:.1}\" width=\"{:.1}\" height=\"24\" fill=\"{color}\"/>",
+                y + 2.0,
+                count as f64 / max * (WIDTH - LABEL * 2.0)
+            )
+            .unwrap();
+        }
+        writeln!(
+            html,
+            "<text x=\"{:.1}\" y=\"{:.1}\">{}/{}/{}/{}</text>",
+            WIDTH - LABEL + 4.0,
+            y + 18.0,
+            crowns.played,
+            crowns.clear,
+            crowns.full_combo,
+            crowns.donderful
+        )
+        .unwrap();
+    }
+    let legend_y = height - 8.0;
+    let legend = [
+        ("游玩", "#e6d9c0"),
+        ("通关", "#8a7f72"),
+        ("全连", "#d49a00"),
+        ("全良", "#d94fa0"),
+    ];
+    for (index, (name, color)) in legend.iter().enumerate() {
+        let x = LABEL + index as f64 * 80.0;
+        writeln!(
+            html,
+            "<rect x=\"{x}\" y=\"{:.1}\" width=\"12\" height=\"12\" fill=\"{color}\"/><text x=\"{:.1}\" y=\"{legend_y}\">{name}</text>",
+            legend_y - 11.0,
+            x + 16.0
+        )
+        .unwrap();
+    }
+    html.push_str("</svg>\n");
+}
+
+/// 按最高分的更新时间排列的最近提升，并和上一次捕获比较分数
+fn recent(
+    html: &mut String,
+    latest: &CaptureRecord,
+    previous: Option<&CaptureRecord>,
+    catalog: Option<&Catalog>,
+) {
+    let previous_scores = previous
+        .map(|record| {
+            record
+                .scores
+                .score_info
+                .iter()
+                .map(|item| ((item.song_no, item.level), item.high_score))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+
+    let mut items = latest.scores.score_info.iter().collect::<Vec<_>>();
+    items.sort_by(|a, b| {
+        b.highscore_datetime
+            .cmp(&a.highscore_datetime)
+            .then(b.update_datetime.cmp(&a.update_datetime))
+    });
+
+    html.push_str("<h2>最近提升</h2>\n<table>\n<thead><tr><th>最高分时间</th><th>最近游玩</th><th>曲名</th><th>难度</th><th>分数</th><th>较上次捕获</th></tr></thead>\n<tbody>\n");
+    for item in items.into_iter().take(RECENT_LIMIT) {
+        let change = match (previous, previous_scores.get(&(item.song_no, item.level))) {
+            (None, _) => String::new(),
+            (Some(_), None) => "<span class=\"up\">新谱面</span>".to_string(),
+            (Some(_), Some(score)) if item.high_score > *score => {
+                format!("<span class=\"up\">+{}</span>", item.high_score - score)
+            }
+            (Some(_), Some(_)) => "<span class=\"muted\">无变化</span>".to_string(),
+        };
+        writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td></tr>",
+            escape_xml(&item.highscore_datetime),
+            escape_xml(&item.update_datetime),
+            escape_xml(&Catalog::detail_for(catalog, item).song_name),
+            songs_score::difficulty_name(item.level),
+            item.high_score,
+            change
+        )
+        .unwrap();
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
+
+/// 最近一次捕获中的所有成绩，可以按曲名、难度、评价和王冠搜索
+fn songs(html: &mut String, latest: &CaptureRecord, catalog: Option<&Catalog>) {
+    let mut items = latest.scores.score_info.iter().collect::<Vec<_>>();
+    items.sort_by_key(|item| {
+        let detail = Catalog::detail_for(catalog, item);
+        (detail.sort, item.song_no, item.level)
+    });
+
+    html.push_str("<h2>全部成绩</h2>\n<input id=\"search\" type=\"search\" placeholder=\"搜索曲名、难度、评价或王冠\">\n");
+    html.push_str("<table id=\"songs\">\n<thead><tr><th>曲名</th><th>难度</th><th>星级</th><th>分数</th><th>评价</th><th>王冠</th><th>良/可/不可</th><th>游玩次数</th></tr></thead>\n<tbody>\n");
+    for item in items {
+        let detail = Catalog::detail_for(catalog, item);
+        let name = &detail.song_name;
+        let difficulty = songs_score::difficulty_name(item.level);
+        let rank = songs_score::rank_name(item.best_score_rank);
+        let crown = item.crown_name();
+        let search = format!(
+            "{} {} {} {} {}",
+            name, detail.song_name_jp, difficulty, rank, crown
+        )
+        .to_lowercase();
+        let stars = detail
+            .stars(item.level)
+            .map_or_else(String::new, |stars| format!("★{}", stars));
+        writeln!(
+            html,
+            "<tr data-search=\"{}\"><td>{}<br><span class=\"muted\">{}</span></td><td>{}</td><td>{}</td><td class=\"number\">{}</td><td>{}</td><td>{}</td><td class=\"number\">{}/{}/{}</td><td class=\"number\">{}</td></tr>",
+            escape_xml(&search),
+            escape_xml(name),
+            escape_xml(&detail.song_name_jp),
+            difficulty,
+            stars,
+            item.high_score,
+            rank,
+            crown,
+            item.good_cnt,
+            item.ok_cnt,
+            item.ng_cnt,
+            item.stage_cnt
+        )
+        .unwrap();
+    }
+    html.push_str("</tbody>\n</table>\n");
+}
@@ -0,0 +1,401 @@
+//! 声明式的抓取规则，决定哪些请求需要处理以及如何处理，接口地址变化时只需要修改配置文件
+
+use std::fmt;
+
+use http::{HeaderMap, Method, Uri, header};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{history::CapturedPart, songs_score};
+
+mod extract;
+mod template;
+pub use extract::Extract;
+pub use template::RelayTemplate;
+
+/// 一条抓取规则，请求的域名、路径和方法都匹配时生效
+#[derive(Debug, Clone, Deserialize)]
+pub struct CaptureRule {
+    /// 规则名称，只用于日志
+    pub name: String,
+    /// 完整的域名，不区分大小写，只有规则中出现的域名才会被解密
+    pub host: String,
+    /// 请求路径，不包含查询参数，支持 `*` 和 `?` 通配符
+    #[serde(default)]
+    pub path: Glob,
+    /// 请求方法，不填时匹配所有方法
+    #[serde(default)]
+    pub method: Option<String>,
+    /// 响应的 `Content-Type`，不包含参数，不填时匹配所有类型
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// 从响应的 JSON 中提取需要的部分，不填时使用整个响应
+    #[serde(default)]
+    pub extract: Option<Extract>,
+    #[serde(flatten)]
+    pub action: RuleAction,
+}
+
+/// 规则匹配后的处理方式，通过 `slot` 在不同的规则之间传递数据
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "handler", rename_all = "snake_case")]
+pub enum RuleAction {
+    /// 把提取到的数据保存到 `slot` 中
+    Store { slot: String },
+    /// 把提取到的数据转换后保存到 `slot` 中
+    Transform { slot: String, transform: Transform },
+    /// 用 `slot` 中的数据代替服务器的响应，`slot` 为空时原样转发，`finish` 为真时随后关闭程序
+    ///
+    /// `slot` 中是 `score_rows` 转换的分数时，可以用 `template` 指定返回的格式
+    Respond {
+        slot: String,
+        #[serde(default)]
+        finish: bool,
+        #[serde(default)]
+        template: Option<RelayTemplate>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    /// 把分数接口的响应转换为同步接口使用的数据，默认为 `v1` 格式的 14 列数组
+    ScoreRows,
+}
+
+impl Transform {
+    fn apply(self, value: Value) -> Result<(Value, CapturedPart), String> {
+        match self {
+            Self::ScoreRows => {
+                let score_data = serde_json::from_value::<songs_score::Response>(value)
+                    .map_err(|err| format!("解析分数数据失败：{err}"))?;
+                tracing::info!("分数响应数据解析成功，正在生成需要返回的数据");
+                for item in &score_data.data.score_info {
+                    tracing::debug!(
+                        "{} 难度 {}（{} 星）：{}",
+                        item.song_detail.song_name,
+                        item.level,
+                        item.stars().map_or("?".to_string(), |x| x.to_string()),
+                        item.high_score
+                    );
+                }
+
+                if score_data.status != 0 {
+                    return Err(format!(
+                        "分数数据返回状态码不为 0，可能是未登录或者其他错误，响应的错误信息为：{}",
+                        score_data.message
+                    ));
+                }
+
+                let rows = RelayTemplate::default().render(&score_data.data.score_info);
+                Ok((rows, CapturedPart::Scores(score_data.data)))
+            }
+        }
+    }
+}
+
+impl CaptureRule {
+    fn matches_request(&self, method: &Method, uri: &Uri) -> bool {
+        uri.host().is_some_and(|host| same_host(&self.host, host))
+            && self.path.matches(uri.path())
+            && self
+                .method
+                .as_ref()
+                .is_none_or(|x| x.eq_ignore_ascii_case(method.as_str()))
+    }
+
+    fn stored_slot(&self) -> Option<&String> {
+        match &self.action {
+            RuleAction::Store { slot } | RuleAction::Transform { slot, .. } => Some(slot),
+            RuleAction::Respond { .. } => None,
+        }
+    }
+
+    fn stores_scores(&self, slot: &str) -> bool {
+        matches!(
+            &self.action,
+            RuleAction::Transform { slot: x, transform: Transform::ScoreRows } if x == slot
+        )
+    }
+
+    /// 响应的类型是否符合规则，不符合时不会处理响应
+    pub fn matches_content_type(&self, headers: &HeaderMap) -> bool {
+        let Some(expected) = &self.content_type else {
+            return true;
+        };
+
+        headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.split(';').next())
+            .is_some_and(|x| x.trim().eq_ignore_ascii_case(expected))
+    }
+
+    /// 解析响应的 JSON，提取并转换后返回需要保存的数据，以及需要合并到捕获记录中的数据
+    pub fn process(&self, body: &[u8]) -> Result<(Value, Option<CapturedPart>), String> {
+        let value = serde_json::from_slice::<Value>(body)
+            .map_err(|err| format!("解析 {} 的响应数据失败：{err}", self.name))?;
+
+        let value = match &self.extract {
+            Some(extract) => extract
+                .apply(&value)
+                .ok_or_else(|| format!("{} 的响应数据中没有 {}", self.name, extract))?,
+            None => value,
+        };
+
+        match &self.action {
+            RuleAction::Transform { transform, .. } => transform
+                .apply(value)
+                .map(|(value, part)| (value, Some(part))),
+            _ => Ok((value, None)),
+        }
+    }
+}
+
+fn same_host(expected: &str, host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    expected.eq_ignore_ascii_case(host)
+}
+
+/// 只支持 `*`（任意多个字符，包括 `/`）和 `?`（单个字符）的通配符
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub struct Glob(Vec<char>);
+
+impl Glob {
+    pub fn matches(&self, text: &str) -> bool {
+        let pattern = &self.0;
+        let text = text.chars().collect::<Vec<_>>();
+
+        let (mut p, mut t) = (0, 0);
+        // 上一个 `*` 的位置以及它当时匹配到的文本位置
+        let mut star = None;
+
+        while t < text.len() {
+            match pattern.get(p) {
+                Some('*') => {
+                    star = Some((p, t));
+                    p += 1;
+                }
+                Some(&c) if c == '?' || c == text[t] => {
+                    p += 1;
+                    t += 1;
+                }
+                _ => match star {
+                    Some((star_p, star_t)) => {
+                        p = star_p + 1;
+                        t = star_t + 1;
+                        star = Some((star_p, star_t + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        pattern[p..].iter().all(|&c| c == '*')
+    }
+}
+
+impl Default for Glob {
+    fn default() -> Self {
+        Self::from("*".to_string())
+    }
+}
+
+impl From<String> for Glob {
+    fn from(pattern: String) -> Self {
+        Self(pattern.chars().collect())
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.iter().collect::<String>())
+    }
+}
+
+/// 原版同步网站请求的地址，只有这个路径会返回分数，同一域名的其它请求正常转发
+const DEFAULT_RELAY_HOST: &str = "www.baidu.com";
+const DEFAULT_RELAY_PATH: &str = "/api/ahfsdafbaqwerhue";
+
+/// 接收分数的网站，是返回 `score` 的 `respond` 规则的简化写法
+#[derive(Debug, Clone, Deserialize)]
+pub struct RelayTarget {
+    /// 规则名称，不填时使用地址
+    #[serde(default)]
+    pub name: Option<String>,
+    pub host: String,
+    /// 完整的请求路径，不包含查询参数
+    pub path: String,
+    #[serde(default = "default_relay_method")]
+    pub method: String,
+    #[serde(default)]
+    pub template: Option<RelayTemplate>,
+    /// 返回分数后关闭程序
+    #[serde(default = "default_relay_finish")]
+    pub finish: bool,
+}
+
+fn default_relay_method() -> String {
+    "GET".to_string()
+}
+
+fn default_relay_finish() -> bool {
+    true
+}
+
+impl RelayTarget {
+    pub fn into_rule(self) -> Result<CaptureRule, String> {
+        if self.host.is_empty() {
+            return Err("同步网站缺少域名".to_string());
+        }
+        if !self.path.starts_with('/') || self.path.contains(['*', '?']) {
+            return Err(format!(
+                "同步网站 {} 的路径需要是以 / 开头的完整路径：{}",
+                self.host, self.path
+            ));
+        }
+
+        Ok(CaptureRule {
+            name: self
+                .name
+                .unwrap_or_else(|| format!("同步接口 {}{}", self.host, self.path)),
+            host: self.host,
+            path: Glob::from(self.path),
+            method: Some(self.method),
+            content_type: None,
+            extract: None,
+            action: RuleAction::Respond {
+                slot: "score".to_string(),
+                finish: self.finish,
+                template: self.template,
+            },
+        })
+    }
+}
+
+/// 用同步网站代替规则中所有返回 `score` 的规则，新的规则放在原来第一条同步规则的位置
+pub fn replace_relay_targets(
+    rules: &mut Vec<CaptureRule>,
+    targets: Vec<RelayTarget>,
+) -> Result<(), String> {
+    let relays = targets
+        .into_iter()
+        .map(RelayTarget::into_rule)
+        .collect::<Result<Vec<_>, _>>()?;
+    let is_relay = |rule: &CaptureRule| matches!(&rule.action, RuleAction::Respond { slot, .. } if slot == "score");
+    let position = rules.iter().position(is_relay).unwrap_or(rules.len());
+    rules.retain(|rule| !is_relay(rule));
+    rules.splice(position..position, relays);
+    Ok(())
+}
+
+/// 内置的规则，配置文件中没有规则时使用
+pub fn default_rules() -> Vec<CaptureRule> {
+    Vec::from([
+        CaptureRule {
+            name: "分数接口".to_string(),
+            host: "wl-taiko.wahlap.net".to_string(),
+            path: Glob::from("/api/user/profile/songscore".to_string()),
+            method: Some("POST".to_string()),
+            content_type: None,
+            extract: None,
+            action: RuleAction::Transform {
+                slot: "score".to_string(),
+                transform: Transform::ScoreRows,
+            },
+        },
+        CaptureRule {
+            name: "同步接口".to_string(),
+            host: DEFAULT_RELAY_HOST.to_string(),
+            path: Glob::from(DEFAULT_RELAY_PATH.to_string()),
+            method: Some("GET".to_string()),
+            content_type: None,
+            extract: None,
+            action: RuleAction::Respond {
+                slot: "score".to_string(),
+                finish: true,
+                template: None,
+            },
+        },
+    ])
+}
+
+/// 按顺序排列的规则，请求使用第一条匹配的规则
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    rules: Vec<CaptureRule>,
+    hosts: Vec<String>,
+}
+
+impl RuleSet {
+    pub fn new(rules: Vec<CaptureRule>) -> Result<Self, String> {
+        if rules.is_empty() {
+            return Err("至少需要一条抓取规则".to_string());
+        }
+
+        let mut hosts: Vec<String> = Vec::new();
+        for rule in &rules {
+            if rule.host.is_empty() {
+                return Err(format!("规则 {} 缺少域名", rule.name));
+            }
+            if !hosts.iter().any(|host| same_host(host, &rule.host)) {
+                hosts.push(rule.host.to_ascii_lowercase());
+            }
+        }
+
+        for rule in &rules {
+            let RuleAction::Respond { slot, template, .. } = &rule.action else {
+                continue;
+            };
+            if !rules.iter().any(|x| x.stored_slot() == Some(slot)) {
+                tracing::warn!("规则 {} 使用的 {} 不会被任何规则保存", rule.name, slot);
+            } else if let Some(template) = template
+                && !rules.iter().any(|x| x.stores_scores(slot))
+            {
+                tracing::warn!(
+                    "规则 {} 指定了同步格式 {}，但是 {} 中不是分数数据，会原样返回",
+                    rule.name,
+                    template.version(),
+                    slot
+                );
+            }
+        }
+
+        Ok(Self { rules, hosts })
+    }
+
+    /// 需要解密的域名，CA 证书也只允许为这些域名签发证书
+    pub fn hosts(&self) -> Vec<&str> {
+        self.hosts.iter().map(String::as_str).collect()
+    }
+
+    /// 其它域名的 CONNECT 请求会作为普通 TCP 隧道转发，不会使用自签名证书解密
+    pub fn intercepts(&self, host: &str) -> bool {
+        self.hosts.iter().any(|x| same_host(x, host))
+    }
+
+    /// 返回第一条匹配请求的规则的序号
+    pub fn match_request(&self, method: &Method, uri: &Uri) -> Option<usize> {
+        self.rules
+            .iter()
+            .position(|rule| rule.matches_request(method, uri))
+    }
+
+    pub fn get(&self, index: usize) -> &CaptureRule {
+        &self.rules[index]
+    }
+
+    /// 保存到 `slot` 的数据是否会被某条规则返回给客户端
+    pub fn is_relayed(&self, slot: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| matches!(&rule.action, RuleAction::Respond { slot: x, .. } if x == slot))
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::new(default_rules()).expect("内置的抓取规则无效")
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt;
+
+use serde_json::Value;
+
+/// 从 JSON 中提取数据的表达式
+///
+/// 以 `/` 开头时按照 JSON Pointer（RFC 6901）解析，例如 `/data/scoreInfo/0`；
+/// 否则按照以 `.` 分隔的字段路径解析，例如 `data.scoreInfo[*].song_no`，
+/// 其中 `[n]` 取数组的第 n 个元素，`[*]` 对数组的每个元素分别取值，缺少字段的元素会被忽略
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extract {
+    source: String,
+    kind: Kind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    Pointer,
+    Path(Vec<Step>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Field(String),
+    Index(usize),
+    Each,
+}
+
+impl Extract {
+    pub fn apply(&self, value: &Value) -> Option<Value> {
+        match &self.kind {
+            Kind::Pointer => value.pointer(&self.source).cloned(),
+            Kind::Path(steps) => apply_steps(value, steps),
+        }
+    }
+}
+
+fn apply_steps(value: &Value, steps: &[Step]) -> Option<Value> {
+    let Some((step, rest)) = steps.split_first() else {
+        return Some(value.clone());
+    };
+
+    match step {
+        Step::Field(name) => apply_steps(value.get(name)?, rest),
+        Step::Index(index) => apply_steps(value.get(index)?, rest),
+        Step::Each => {
+            let items = value.as_array()?;
+            Some(Value::Array(
+                items
+                    .iter()
+                    .filter_map(|item| apply_steps(item, rest))
+                    .collect(),
+            ))
+        }
+    }
+}
+
+fn parse_path(source: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+
+    for segment in source.split('.') {
+        let (field, mut indexes) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+        if field.is_empty() && (indexes.is_empty() || !steps.is_empty()) {
+            return Err(format!("提取表达式中有空的字段名：{source}"));
+        }
+        if !field.is_empty() {
+            steps.push(Step::Field(field.to_string()));
+        }
+
+        while !indexes.is_empty() {
+            let (index, rest) = indexes
+                .strip_prefix('[')
+                .and_then(|x| x.split_once(']'))
+                .ok_or_else(|| format!("提取表达式中的括号不匹配：{source}"))?;
+            steps.push(match index {
+                "*" => Step::Each,
+                index => Step::Index(
+                    index
+                        .parse()
+                        .map_err(|_| format!("提取表达式中的下标无效：{source}"))?,
+                ),
+            });
+            indexes = rest;
+        }
+    }
+
+    Ok(steps)
+}
+
+impl TryFrom<String> for Extract {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let kind = if source.is_empty() || source.starts_with('/') {
+            Kind::Pointer
+        } else {
+            Kind::Path(parse_path(&source)?)
+        };
+        Ok(Self { source, kind })
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Extract {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Self::try_from(source).map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for Extract {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
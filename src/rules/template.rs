@@ -0,0 +1,257 @@
+use std::collections::BTreeSet;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{history, songs_score};
+
+/// 内置格式 `v1` 的列，也就是原版程序使用的 14 列数组
+const V1_COLUMNS: [&str; 14] = [
+    "song_no",
+    "level",
+    "high_score",
+    "best_score_rank",
+    "good_cnt",
+    "ok_cnt",
+    "ng_cnt",
+    "pound_cnt",
+    "combo_cnt",
+    "stage_cnt",
+    "clear_cnt",
+    "full_combo_cnt",
+    "dondaful_combo_cnt",
+    "update_datetime",
+];
+
+/// 同步接口返回的数据格式
+///
+/// 可以写内置格式的版本名称（目前只有 `"v1"`），也可以写一个对象自定义每一列，例如
+/// `{"version": "fork-v2", "row": "object", "wrap": "scores", "columns": ["song_no", "level"]}`。
+/// 版本名称用于区分同一个接收网站的不同分支，会写在响应头 `X-Relay-Version` 中，不同的规则可以使用不同的格式
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Value")]
+pub struct RelayTemplate {
+    version: String,
+    row: RowShape,
+    wrap: Option<String>,
+    columns: Vec<Column>,
+}
+
+/// 每条成绩输出为数组还是以列名为键的对象
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RowShape {
+    #[default]
+    Array,
+    Object,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "ColumnSpec")]
+pub struct Column {
+    /// [`songs_score::ScoreInfo::field`] 支持的字段
+    field: String,
+    /// 对象中的键，不填时使用字段名称
+    name: String,
+    transform: FieldTransform,
+}
+
+/// 列可以只写字段名称
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColumnSpec {
+    Field(String),
+    Full {
+        field: String,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        transform: FieldTransform,
+    },
+}
+
+impl From<ColumnSpec> for Column {
+    fn from(spec: ColumnSpec) -> Self {
+        match spec {
+            ColumnSpec::Field(field) => Self {
+                name: field.clone(),
+                field,
+                transform: FieldTransform::None,
+            },
+            ColumnSpec::Full {
+                field,
+                name,
+                transform,
+            } => Self {
+                name: name.unwrap_or_else(|| field.clone()),
+                field,
+                transform,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldTransform {
+    /// 保持原样
+    #[default]
+    None,
+    /// 转换为字符串，空值为空字符串
+    String,
+    /// 把字符串转换为数字，无法转换时为 null
+    Number,
+    /// 只保留时间中的日期，例如 `2024-11-02`
+    Date,
+    /// 把北京时间转换为 Unix 时间戳（秒）
+    Timestamp,
+    /// 非 0 的数字和非空字符串为 true
+    Flag,
+}
+
+impl FieldTransform {
+    fn apply(self, value: Value) -> Value {
+        match (self, value) {
+            (Self::None, value) => value,
+            (Self::String, Value::String(s)) => Value::String(s),
+            (Self::String, Value::Null) => Value::String(String::new()),
+            (Self::String, value) => Value::String(value.to_string()),
+            (Self::Number, Value::String(s)) => {
+                let s = s.trim();
+                s.parse::<i64>()
+                    .map(Value::from)
+                    .or_else(|_| s.parse::<f64>().map(Value::from))
+                    .unwrap_or(Value::Null)
+            }
+            (Self::Number, value @ Value::Number(_)) => value,
+            (Self::Number, _) => Value::Null,
+            (Self::Date, Value::String(s)) => Value::String(s.get(..10).unwrap_or(&s).to_string()),
+            (Self::Timestamp, Value::String(s)) => {
+                history::parse_timestamp(&s).map_or(Value::Null, Value::from)
+            }
+            (Self::Date | Self::Timestamp, _) => Value::Null,
+            (Self::Flag, Value::Number(n)) => Value::Bool(n.as_f64().is_some_and(|x| x != 0.0)),
+            (Self::Flag, Value::String(s)) => Value::Bool(!s.is_empty()),
+            (Self::Flag, value) => Value::Bool(value.as_bool().unwrap_or(false)),
+        }
+    }
+}
+
+/// 自定义格式，和内置格式使用相同的字段
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CustomTemplate {
+    version: String,
+    #[serde(default)]
+    row: RowShape,
+    #[serde(default)]
+    wrap: Option<String>,
+    columns: Vec<Column>,
+}
+
+impl RelayTemplate {
+    /// 内置的格式，版本名称不存在时返回 `None`
+    pub fn builtin(version: &str) -> Option<Self> {
+        match version {
+            "v1" => Some(Self {
+                version: version.to_string(),
+                row: RowShape::Array,
+                wrap: None,
+                columns: V1_COLUMNS
+                    .iter()
+                    .map(|field| ColumnSpec::Field(field.to_string()).into())
+                    .collect(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// 按格式生成同步接口返回的数据
+    pub fn render(&self, items: &[songs_score::ScoreInfo]) -> Value {
+        let rows = items
+            .iter()
+            .map(|item| {
+                let values = self.columns.iter().map(|column| {
+                    let value = item.field(&column.field).unwrap_or_default();
+                    (column, column.transform.apply(value))
+                });
+                match self.row {
+                    RowShape::Array => values.map(|(_, value)| value).collect(),
+                    RowShape::Object => Value::Object(
+                        values
+                            .map(|(column, value)| (column.name.clone(), value))
+                            .collect(),
+                    ),
+                }
+            })
+            .collect();
+
+        match &self.wrap {
+            Some(key) => Value::Object([(key.clone(), Value::Array(rows))].into_iter().collect()),
+            None => Value::Array(rows),
+        }
+    }
+}
+
+impl Default for RelayTemplate {
+    fn default() -> Self {
+        Self::builtin("v1").unwrap()
+    }
+}
+
+impl TryFrom<Value> for RelayTemplate {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let template = match value {
+            Value::String(version) => {
+                return Self::builtin(&version).ok_or_else(|| format!("未知的同步格式 {version}"));
+            }
+            value => serde_json::from_value::<CustomTemplate>(value)
+                .map_err(|err| format!("同步格式无效：{err}"))?,
+        };
+
+        if template.version.trim().is_empty() {
+            return Err("同步格式需要填写版本名称".to_string());
+        }
+        if Self::builtin(&template.version).is_some() {
+            return Err(format!(
+                "{} 是内置的同步格式，自定义格式需要使用其它版本名称",
+                template.version
+            ));
+        }
+        if template.columns.is_empty() {
+            return Err(format!("同步格式 {} 至少需要一列", template.version));
+        }
+
+        let item = songs_score::ScoreInfo::default();
+        let mut names = BTreeSet::new();
+        for column in &template.columns {
+            if item.field(&column.field).is_none() {
+                return Err(format!(
+                    "同步格式 {} 中的字段 {} 不存在，可用的字段：{}",
+                    template.version,
+                    column.field,
+                    songs_score::field_names().join("、")
+                ));
+            }
+            if template.row == RowShape::Object && !names.insert(column.name.as_str()) {
+                return Err(format!(
+                    "同步格式 {} 中的列名 {} 重复",
+                    template.version, column.name
+                ));
+            }
+        }
+
+        Ok(Self {
+            version: template.version,
+            row: template.row,
+            wrap: template.wrap,
+            columns: template.columns,
+        })
+    }
+}
@@ -0,0 +1,244 @@
+//! SOCKS5 入口，连接会通过 CONNECT 请求转交给 HTTP 代理，与 HTTP 代理共用同一套拦截和处理流程
+//!
+//! 在本地解析域名的客户端只会给出 IP 地址，这时根据 TLS ClientHello 中的 SNI 找回域名
+
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{rules::RuleSet, upstream::UpstreamProxy};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const NO_ACCEPTABLE_METHODS: u8 = 0xFF;
+const CMD_CONNECT: u8 = 1;
+
+const REPLY_SUCCEEDED: u8 = 0;
+const REPLY_HOST_UNREACHABLE: u8 = 4;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 7;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+const ADDRESS_IPV4: u8 = 1;
+const ADDRESS_DOMAIN: u8 = 3;
+const ADDRESS_IPV6: u8 = 4;
+
+/// 等待客户端发送 ClientHello 的时间，有些协议由服务器先发送数据
+const CLIENT_HELLO_TIMEOUT: Duration = Duration::from_secs(1);
+const TLS_HANDSHAKE: u8 = 0x16;
+const TLS_CLIENT_HELLO: u8 = 1;
+const TLS_EXTENSION_SERVER_NAME: u16 = 0;
+
+/// 持续接受 SOCKS5 连接，直到任务被取消
+pub async fn serve(listener: TcpListener, http_proxy_addr: SocketAddr, rules: RuleSet) {
+    let http_proxy = UpstreamProxy::Http {
+        host: http_proxy_addr.ip().to_string(),
+        port: http_proxy_addr.port(),
+    };
+
+    loop {
+        let (stream, client_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::warn!("SOCKS5 代理无法接受连接：{}", err);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+
+        let http_proxy = http_proxy.clone();
+        let rules = rules.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_client(stream, &http_proxy, &rules).await {
+                tracing::debug!("SOCKS5 连接 {} 已断开：{}", client_addr, err);
+            }
+        });
+    }
+}
+
+async fn reply(stream: &mut TcpStream, code: u8) -> io::Result<()> {
+    stream
+        .write_all(&[VERSION, code, 0, 1, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+async fn handle_client(
+    mut stream: TcpStream,
+    http_proxy: &UpstreamProxy,
+    rules: &RuleSet,
+) -> io::Result<()> {
+    let mut greeting = [0; 2];
+    stream.read_exact(&mut greeting).await?;
+    if greeting[0] != VERSION {
+        return Err(io::Error::other("不是 SOCKS5 请求"));
+    }
+    let mut methods = vec![0; greeting[1] as usize];
+    stream.read_exact(&mut methods).await?;
+
+    if !methods.contains(&NO_AUTH) {
+        stream.write_all(&[VERSION, NO_ACCEPTABLE_METHODS]).await?;
+        return Err(io::Error::other("客户端要求认证"));
+    }
+    stream.write_all(&[VERSION, NO_AUTH]).await?;
+
+    let mut request = [0; 4];
+    stream.read_exact(&mut request).await?;
+    let [_, command, _, address_type] = request;
+
+    let host = match address_type {
+        ADDRESS_IPV4 => Ipv4Addr::from(stream.read_u32().await?).to_string(),
+        ADDRESS_DOMAIN => {
+            let mut host = vec![0; stream.read_u8().await? as usize];
+            stream.read_exact(&mut host).await?;
+            String::from_utf8(host).map_err(io::Error::other)?
+        }
+        ADDRESS_IPV6 => Ipv6Addr::from(stream.read_u128().await?).to_string(),
+        _ => {
+            reply(&mut stream, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(io::Error::other("未知的地址类型"));
+        }
+    };
+    let port = stream.read_u16().await?;
+
+    if command != CMD_CONNECT {
+        reply(&mut stream, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(io::Error::other("只支持 CONNECT 命令"));
+    }
+
+    if address_type != ADDRESS_DOMAIN {
+        return handle_ip_target(stream, http_proxy, rules, host, port).await;
+    }
+
+    let mut server = match http_proxy.connect(&host, port).await {
+        Ok(server) => server,
+        Err(err) => {
+            reply(&mut stream, REPLY_HOST_UNREACHABLE).await?;
+            return Err(err);
+        }
+    };
+    reply(&mut stream, REPLY_SUCCEEDED).await?;
+
+    tokio::io::copy_bidirectional(&mut stream, &mut server).await?;
+    Ok(())
+}
+
+/// 目标是 IP 地址时，先回复连接成功再读取 ClientHello，SNI 是需要拦截的域名时按域名转交给 HTTP 代理
+///
+/// 之后才连接 HTTP 代理，因此无法连接时客户端只会看到连接被关闭
+async fn handle_ip_target(
+    mut stream: TcpStream,
+    http_proxy: &UpstreamProxy,
+    rules: &RuleSet,
+    ip: String,
+    port: u16,
+) -> io::Result<()> {
+    reply(&mut stream, REPLY_SUCCEEDED).await?;
+
+    let hello = read_client_hello(&mut stream).await?;
+    let host = match client_hello_sni(&hello) {
+        Some(name) if rules.intercepts(&name) => {
+            tracing::info!(
+                "SOCKS5 客户端直接连接了 {}，根据 TLS SNI 识别为 {}",
+                ip,
+                name
+            );
+            name
+        }
+        _ => {
+            tracing::debug!(
+                "SOCKS5 客户端直接连接了 {}:{}，不是需要拦截的域名，如果无法捕获成绩，请在客户端中开启远程 DNS 解析（socks5h）",
+                ip,
+                port
+            );
+            ip
+        }
+    };
+
+    let mut server = http_proxy.connect(&host, port).await?;
+    server.write_all(&hello).await?;
+    tokio::io::copy_bidirectional(&mut stream, &mut server).await?;
+    Ok(())
+}
+
+/// 读取客户端发送的第一个 TLS 记录，不是 TLS 或者超时时返回已经读取到的数据
+async fn read_client_hello(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let read = async {
+        let mut buf = [0; 4096];
+        loop {
+            let expected = match data.get(..5) {
+                Some(header) if header[0] == TLS_HANDSHAKE => {
+                    5 + usize::from(u16::from_be_bytes([header[3], header[4]]))
+                }
+                Some(_) => return io::Result::Ok(()),
+                None => 5,
+            };
+            if data.len() >= expected {
+                return Ok(());
+            }
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            data.extend_from_slice(&buf[..n]);
+        }
+    };
+    if let Ok(result) = tokio::time::timeout(CLIENT_HELLO_TIMEOUT, read).await {
+        result?;
+    }
+    Ok(data)
+}
+
+/// 从 ClientHello 的 server_name 扩展中取出域名
+fn client_hello_sni(data: &[u8]) -> Option<String> {
+    if *data.first()? != TLS_HANDSHAKE {
+        return None;
+    }
+    let mut hello = data.get(5..)?;
+    if *hello.first()? != TLS_CLIENT_HELLO {
+        return None;
+    }
+    // 握手消息的类型和长度、客户端版本以及 32 字节的随机数
+    hello = hello.get(4 + 2 + 32..)?;
+    split_vec(&mut hello, 1)?; // session_id
+    split_vec(&mut hello, 2)?; // cipher_suites
+    split_vec(&mut hello, 1)?; // compression_methods
+    let mut extensions = split_vec(&mut hello, 2)?;
+
+    while !extensions.is_empty() {
+        let extension_type = u16::from_be_bytes([*extensions.first()?, *extensions.get(1)?]);
+        extensions = &extensions[2..];
+        let mut extension = split_vec(&mut extensions, 2)?;
+        if extension_type != TLS_EXTENSION_SERVER_NAME {
+            continue;
+        }
+
+        let mut names = split_vec(&mut extension, 2)?;
+        while !names.is_empty() {
+            let name_type = names[0];
+            names = &names[1..];
+            let name = split_vec(&mut names, 2)?;
+            if name_type == 0 {
+                return String::from_utf8(name.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// 取出以 `len_size` 字节的长度开头的数据
+fn split_vec<'a>(data: &mut &'a [u8], len_size: usize) -> Option<&'a [u8]> {
+    let len = data
+        .get(..len_size)?
+        .iter()
+        .fold(0, |len, &x| len << 8 | usize::from(x));
+    let value = data.get(len_size..len_size + len)?;
+    *data = &data[len_size + len..];
+    Some(value)
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Response {
     pub status: i64,
@@ -10,7 +10,7 @@ pub struct Response {
     pub data: Data,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Data {
     pub userid: String,
@@ -18,7 +18,7 @@ pub struct Data {
     pub score_info: Vec<ScoreInfo>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct ScoreInfo {
     pub song_no: i64,
@@ -42,7 +42,7 @@ pub struct ScoreInfo {
     pub song_detail: SongDetail,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 #[serde(default)]
 pub struct SongDetail {
     pub sort: i64,
@@ -52,9 +52,184 @@ pub struct SongDetail {
     pub song_name_jp: String,
     pub song_name: String,
     pub family: String,
-    // pub level_1: u8,
-    // pub level_2: u8,
-    // pub level_3: u8,
-    // pub level_4: u8,
-    // pub level_5: serde_json::Value,
+    /// 各难度的星级，没有谱面时为空
+    #[serde(deserialize_with = "lenient_stars")]
+    pub level_1: Option<u8>,
+    #[serde(deserialize_with = "lenient_stars")]
+    pub level_2: Option<u8>,
+    #[serde(deserialize_with = "lenient_stars")]
+    pub level_3: Option<u8>,
+    #[serde(deserialize_with = "lenient_stars")]
+    pub level_4: Option<u8>,
+    #[serde(deserialize_with = "lenient_stars")]
+    pub level_5: Option<u8>,
+}
+
+impl SongDetail {
+    /// 从同步接口的数组导入的成绩没有歌曲信息
+    pub fn is_empty(&self) -> bool {
+        self.song_name.is_empty() && self.song_name_jp.is_empty()
+    }
+
+    /// 指定难度（1 为简单，5 为里魔王）的星级
+    pub fn stars(&self, level: i64) -> Option<u8> {
+        match level {
+            1 => self.level_1,
+            2 => self.level_2,
+            3 => self.level_3,
+            4 => self.level_4,
+            5 => self.level_5,
+            _ => None,
+        }
+    }
+}
+
+/// 服务器返回的星级可能是数字、字符串、空字符串或者 null，无法识别时视为没有谱面
+fn lenient_stars<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u8>, D::Error> {
+    let stars = match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(n) => n
+            .as_u64()
+            .or_else(|| n.as_f64().filter(|x| x.fract() == 0.0).map(|x| x as u64)),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    };
+
+    Ok(stars.and_then(|x| u8::try_from(x).ok()).filter(|x| *x > 0))
+}
+
+impl ScoreInfo {
+    /// 这条成绩所在难度的星级
+    pub fn stars(&self) -> Option<u8> {
+        self.song_detail.stars(self.level)
+    }
+
+    /// 王冠的名称，全良优先于全连，全连优先于通关
+    pub fn crown_name(&self) -> &'static str {
+        if self.dondaful_combo_cnt > 0 {
+            "全良"
+        } else if self.full_combo_cnt > 0 {
+            "全连"
+        } else if self.clear_cnt > 0 {
+            "通关"
+        } else {
+            "未通关"
+        }
+    }
+
+    /// 按名称读取字段，`SongDetail` 中的字段可以省略 `song_detail.` 前缀，
+    /// 另外支持 `stars`（星级）和 `crown`（王冠名称），字段不存在时返回 `None`
+    pub fn field(&self, name: &str) -> Option<serde_json::Value> {
+        self.field_with(&self.song_detail, name)
+    }
+
+    /// 和 [`Self::field`] 相同，但歌曲信息从 `detail` 中读取，通常是歌曲目录中的数据
+    pub fn field_with(&self, detail: &SongDetail, name: &str) -> Option<serde_json::Value> {
+        use serde_json::Value;
+
+        let stars = |stars: Option<u8>| stars.map_or(Value::Null, Value::from);
+        let value = match name.strip_prefix("song_detail.").unwrap_or(name) {
+            "song_no" => self.song_no.into(),
+            "level" => self.level.into(),
+            "high_score" => self.high_score.into(),
+            "best_score_rank" => self.best_score_rank.into(),
+            "good_cnt" => self.good_cnt.into(),
+            "ok_cnt" => self.ok_cnt.into(),
+            "ng_cnt" => self.ng_cnt.into(),
+            "pound_cnt" => self.pound_cnt.into(),
+            "combo_cnt" => self.combo_cnt.into(),
+            "option_flg" => self.option_flg.clone().into(),
+            "tone_flg" => self.tone_flg.clone().into(),
+            "stage_cnt" => self.stage_cnt.into(),
+            "clear_cnt" => self.clear_cnt.into(),
+            "full_combo_cnt" => self.full_combo_cnt.into(),
+            "dondaful_combo_cnt" => self.dondaful_combo_cnt.into(),
+            "highscore_datetime" => self.highscore_datetime.clone().into(),
+            "highscore_mode" => self.highscore_mode.into(),
+            "update_datetime" => self.update_datetime.clone().into(),
+            "sort" => detail.sort.into(),
+            "id" => detail.id.into(),
+            "open_day" => detail.open_day.clone().into(),
+            "type_" => detail.type_.clone().into(),
+            "song_name_jp" => detail.song_name_jp.clone().into(),
+            "song_name" => detail.song_name.clone().into(),
+            "family" => detail.family.clone().into(),
+            "level_1" => stars(detail.level_1),
+            "level_2" => stars(detail.level_2),
+            "level_3" => stars(detail.level_3),
+            "level_4" => stars(detail.level_4),
+            "level_5" => stars(detail.level_5),
+            "stars" => stars(detail.stars(self.level)),
+            "crown" => self.crown_name().into(),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    /// 从同步接口 `v1` 格式的 14 列数组恢复成绩，歌曲信息为空
+    pub fn from_relay_row(row: &serde_json::Value) -> Option<Self> {
+        let row = row.as_array().filter(|row| row.len() == 14)?;
+        let number = |index: usize| row[index].as_i64();
+        Some(Self {
+            song_no: number(0)?,
+            level: number(1)?,
+            high_score: number(2)?,
+            best_score_rank: number(3)?,
+            good_cnt: number(4)?,
+            ok_cnt: number(5)?,
+            ng_cnt: number(6)?,
+            pound_cnt: number(7)?,
+            combo_cnt: number(8)?,
+            stage_cnt: number(9)?,
+            clear_cnt: number(10)?,
+            full_combo_cnt: number(11)?,
+            dondaful_combo_cnt: number(12)?,
+            update_datetime: row[13].as_str()?.to_string(),
+            ..Default::default()
+        })
+    }
+}
+
+/// [`ScoreInfo::field`] 支持的所有字段名称
+pub fn field_names() -> Vec<String> {
+    let serde_json::Value::Object(item) = serde_json::to_value(ScoreInfo::default()).unwrap()
+    else {
+        unreachable!()
+    };
+    let mut names = item
+        .into_iter()
+        .flat_map(|(key, value)| match value {
+            serde_json::Value::Object(detail) => detail.keys().cloned().collect(),
+            _ => vec![key],
+        })
+        .collect::<Vec<_>>();
+    names.extend(["stars".to_string(), "crown".to_string()]);
+    names
+}
+
+/// 难度的中文名称，`level` 为 1 到 5
+pub fn difficulty_name(level: i64) -> &'static str {
+    match level {
+        1 => "简单",
+        2 => "普通",
+        3 => "困难",
+        4 => "魔王",
+        5 => "里魔王",
+        _ => "未知难度",
+    }
+}
+
+/// 评价的名称，`best_score_rank` 为 2 到 8，分别对应白粋到極
+pub fn rank_name(rank: i64) -> &'static str {
+    match rank {
+        2 => "白粋",
+        3 => "铜粋",
+        4 => "银粋",
+        5 => "金雅",
+        6 => "桃雅",
+        7 => "紫雅",
+        8 => "極",
+        _ => "无评价",
+    }
 }
@@ -0,0 +1,126 @@
+//! 根据一次捕获的分数数据统计王冠、评价、准确率等数据
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+    catalog::Catalog,
+    songs_score::{self, ScoreInfo},
+};
+
+/// 各种王冠的数量，全连包括全良，通关包括全连
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CrownCounts {
+    pub played: usize,
+    pub clear: usize,
+    pub full_combo: usize,
+    pub donderful: usize,
+}
+
+impl CrownCounts {
+    fn add(&mut self, item: &ScoreInfo) {
+        self.played += 1;
+        if item.clear_cnt > 0 || item.full_combo_cnt > 0 || item.dondaful_combo_cnt > 0 {
+            self.clear += 1;
+        }
+        if item.full_combo_cnt > 0 || item.dondaful_combo_cnt > 0 {
+            self.full_combo += 1;
+        }
+        if item.dondaful_combo_cnt > 0 {
+            self.donderful += 1;
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Accuracy {
+    pub good: i64,
+    pub ok: i64,
+    pub ng: i64,
+    /// 良占所有判定的比例，没有判定时为 0
+    pub good_rate: f64,
+    pub ok_rate: f64,
+    pub ng_rate: f64,
+}
+
+impl Accuracy {
+    fn new(good: i64, ok: i64, ng: i64) -> Self {
+        let total = (good + ok + ng) as f64;
+        let rate = |x: i64| if total > 0.0 { x as f64 / total } else { 0.0 };
+        Self {
+            good,
+            ok,
+            ng,
+            good_rate: rate(good),
+            ok_rate: rate(ok),
+            ng_rate: rate(ng),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    /// 以难度（1 为简单，5 为里魔王）为键
+    pub by_difficulty: BTreeMap<i64, CrownCounts>,
+    /// 以星级为键，不区分难度，没有星级的谱面不计入
+    pub by_stars: BTreeMap<u8, CrownCounts>,
+    /// 以 `best_score_rank` 为键
+    pub ranks: BTreeMap<i64, usize>,
+    pub accuracy: Accuracy,
+    /// 连打总数
+    pub drumroll_hits: i64,
+    /// 以歌曲分类为键的游玩次数
+    pub plays_by_genre: BTreeMap<String, i64>,
+}
+
+impl Stats {
+    /// 星级和分类以歌曲目录为准，见 [`Catalog::detail_for`]
+    pub fn new(data: &songs_score::Data, catalog: Option<&Catalog>) -> Self {
+        let mut stats = Self::default();
+        let (mut good, mut ok, mut ng) = (0, 0, 0);
+
+        for item in &data.score_info {
+            let detail = Catalog::detail_for(catalog, item);
+            stats.by_difficulty.entry(item.level).or_default().add(item);
+            if let Some(stars) = detail.stars(item.level) {
+                stats.by_stars.entry(stars).or_default().add(item);
+            }
+            *stats.ranks.entry(item.best_score_rank).or_default() += 1;
+
+            good += item.good_cnt;
+            ok += item.ok_cnt;
+            ng += item.ng_cnt;
+            stats.drumroll_hits += item.pound_cnt;
+
+            let genre = match detail.family.as_str() {
+                "" => "其他".to_string(),
+                family => family.to_string(),
+            };
+            *stats.plays_by_genre.entry(genre).or_default() += item.stage_cnt;
+        }
+
+        stats.accuracy = Accuracy::new(good, ok, ng);
+        stats
+    }
+
+    /// 单行的摘要，只包括魔王和里魔王，用于在窗口中显示
+    pub fn summary(&self) -> String {
+        let mut parts = self
+            .by_difficulty
+            .iter()
+            .filter(|(level, _)| **level >= 4)
+            .map(|(level, crowns)| {
+                format!(
+                    "{} 通关 {}/全连 {}/全良 {}",
+                    songs_score::difficulty_name(*level),
+                    crowns.clear,
+                    crowns.full_combo,
+                    crowns.donderful
+                )
+            })
+            .collect::<Vec<_>>();
+        parts.push(format!("良率 {:.1}%", self.accuracy.good_rate * 100.0));
+        parts.join("，")
+    }
+}
@@ -0,0 +1,154 @@
+//! 下次练习目标，从一次捕获中找出离下一个评价或王冠最近的谱面
+
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{
+    catalog::Catalog,
+    songs_score::{self, ScoreInfo},
+};
+
+/// 各评价需要的分数，`best_score_rank` 为 2 到 8，分别对应白粋到極
+const RANK_SCORES: [(i64, i64); 7] = [
+    (2, 500_000),
+    (3, 600_000),
+    (4, 700_000),
+    (5, 800_000),
+    (6, 900_000),
+    (7, 950_000),
+    (8, 1_000_000),
+];
+/// 离下一个评价的分数差距在这个范围内才会推荐
+const RANK_MARGIN: i64 = 20_000;
+/// 不可的数量在这个范围内才会推荐全连
+const NG_LIMIT: i64 = 10;
+/// 可的数量在这个范围内才会推荐全良
+const OK_LIMIT: i64 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TargetKind {
+    /// 差 `points` 分达到评价 `rank`
+    Rank { rank: i64, points: i64 },
+    /// 已通关，最高分的那次游玩中有 `ng` 个不可
+    FullCombo { ng: i64 },
+    /// 已全连，最高分的那次游玩中有 `ok` 个可
+    Donderful { ok: i64 },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Target {
+    pub song_no: i64,
+    pub level: i64,
+    pub stars: Option<u8>,
+    pub song_name: String,
+    #[serde(flatten)]
+    pub kind: TargetKind,
+    /// 离目标的距离，0 到 1 之间，越小越接近
+    pub distance: f64,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}（{}",
+            self.song_name,
+            songs_score::difficulty_name(self.level)
+        )?;
+        if let Some(stars) = self.stars {
+            write!(f, " ★{}", stars)?;
+        }
+        match &self.kind {
+            TargetKind::Rank { rank, points } => write!(
+                f,
+                "）：再得 {} 分即可达到{}",
+                points,
+                songs_score::rank_name(*rank)
+            ),
+            TargetKind::FullCombo { ng } => write!(f, "）：只差 {} 个不可就能全连", ng),
+            TargetKind::Donderful { ok } => write!(f, "）：只差 {} 个可就能全良", ok),
+        }
+    }
+}
+
+/// 玩家的星级，即全连过的最高星级，没有全连过时为通关过的最高星级
+pub fn player_stars(data: &songs_score::Data, catalog: Option<&Catalog>) -> Option<u8> {
+    let max_stars = |filter: fn(&ScoreInfo) -> bool| {
+        data.score_info
+            .iter()
+            .filter(|item| filter(item))
+            .filter_map(|item| Catalog::detail_for(catalog, item).stars(item.level))
+            .max()
+    };
+    max_stars(|item| item.full_combo_cnt > 0 || item.dondaful_combo_cnt > 0)
+        .or_else(|| max_stars(|item| item.clear_cnt > 0))
+}
+
+/// 按离目标的距离从近到远排列，距离相同时星级高的在前
+///
+/// 全连目标只包括不超过玩家星级的谱面，避免推荐明显超出能力的谱面
+pub fn next_targets(data: &songs_score::Data, catalog: Option<&Catalog>) -> Vec<Target> {
+    let player_stars = player_stars(data, catalog);
+    let mut targets = Vec::new();
+
+    for item in &data.score_info {
+        let detail = Catalog::detail_for(catalog, item);
+        let stars = detail.stars(item.level);
+        let mut push = |kind, distance| {
+            targets.push(Target {
+                song_no: item.song_no,
+                level: item.level,
+                stars,
+                song_name: detail.song_name.clone(),
+                kind,
+                distance,
+            })
+        };
+
+        if let Some((rank, score)) = RANK_SCORES
+            .iter()
+            .find(|(_, score)| *score > item.high_score)
+        {
+            let points = score - item.high_score;
+            if points <= RANK_MARGIN {
+                push(
+                    TargetKind::Rank {
+                        rank: *rank,
+                        points,
+                    },
+                    points as f64 / RANK_MARGIN as f64,
+                );
+            }
+        }
+
+        let full_combo = item.full_combo_cnt > 0 || item.dondaful_combo_cnt > 0;
+        if item.clear_cnt > 0
+            && !full_combo
+            && (1..=NG_LIMIT).contains(&item.ng_cnt)
+            && stars <= player_stars
+        {
+            push(
+                TargetKind::FullCombo { ng: item.ng_cnt },
+                item.ng_cnt as f64 / NG_LIMIT as f64,
+            );
+        }
+
+        if full_combo && item.dondaful_combo_cnt == 0 && (1..=OK_LIMIT).contains(&item.ok_cnt) {
+            push(
+                TargetKind::Donderful { ok: item.ok_cnt },
+                item.ok_cnt as f64 / OK_LIMIT as f64,
+            );
+        }
+    }
+
+    targets.sort_by(|a, b| {
+        a.distance
+            .total_cmp(&b.distance)
+            .then(b.stars.cmp(&a.stars))
+            .then(a.song_no.cmp(&b.song_no))
+            .then(a.level.cmp(&b.level))
+    });
+    targets
+}
@@ -0,0 +1,1712 @@
+mod mock;
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use http::{Method, StatusCode};
+use mock::{
+    MockUpstream, MockWahlap, OTHER_RELAY_HOST, SongScoreBody, TestProxy, connect_via, get_direct,
+    send_https,
+};
+use serde_json::json;
+
+use hudsucker::rcgen::{
+    BasicConstraints, CertificateParams, DnType, GeneralSubtree, IsCa, KeyPair,
+};
+
+use crate::capture::CaptureLimits;
+use crate::catalog::Catalog;
+use crate::goals::GoalState;
+use crate::history::History;
+use crate::os::{CaProblem, CertStore, FakeCertStore, FakeProxy, SystemProxy, key_file};
+use crate::rules::{Extract, Glob, RuleSet};
+
+const SONGSCORE_FIXTURE: &str = include_str!("tests/fixtures/songscore.json");
+const SONGSCORE_URL: &str = "https://wl-taiko.wahlap.net/api/user/profile/songscore";
+const FETCH_SCORE_URL: &str = "https://www.baidu.com/api/ahfsdafbaqwerhue";
+const OTHER_WAHLAP_URL: &str = "https://wl-taiko.wahlap.net/api/user/profile/other";
+
+#[rustfmt::skip]
+fn expected_rows() -> serde_json::Value {
+    json!([
+        [1, 4, 1002340, 8, 590, 12, 0, 37, 602, 15, 14, 6, 0, "2024-11-02 20:15:31"],
+        [2, 3, 856120, 5, 401, 88, 9, 12, 211, 3, 2, 0, 0, "2024-10-30 21:44:05"],
+    ])
+}
+
+/// 内置规则中需要解密的域名
+fn default_hosts() -> Vec<&'static str> {
+    static RULES: LazyLock<RuleSet> = LazyLock::new(RuleSet::default);
+    RULES.hosts()
+}
+
+fn temp_config_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "taiko-score-getter-test-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[tokio::test]
+async fn fetch_score_relays_captured_scores() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let proxy = TestProxy::start(&wahlap).await;
+
+    let (status, body) = proxy.request(Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        SONGSCORE_FIXTURE.as_bytes(),
+        "分数接口的响应必须原样返回"
+    );
+
+    let (status, body) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(rows, expected_rows());
+    assert!(
+        rows.as_array()
+            .unwrap()
+            .iter()
+            .all(|row| row.as_array().unwrap().len() == 14)
+    );
+}
+
+#[tokio::test]
+async fn captured_scores_are_saved_to_history() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let config_dir = temp_config_dir("history");
+    let proxy = TestProxy::start_with_data_dir(&wahlap, RuleSet::default(), &config_dir).await;
+
+    let (status, _) = proxy.request(Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let records = History::new(&config_dir).load_all().await.unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].scores.userid, "1000123456");
+    assert_eq!(records[0].scores.score_info.len(), 2);
+
+    let catalog = Catalog::load(&config_dir).await.unwrap();
+    assert_eq!(catalog.search("").len(), 2);
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn goals_are_evaluated_on_capture() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let config_dir = temp_config_dir("goals");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    let config = json!({
+        "goals": [
+            { "name": "魔王十星全连", "crown": "full_combo", "level": 4, "stars": 10, "count": 1 },
+            { "name": "夏祭金雅", "rank": "金雅", "song_no": 2 },
+            { "name": "流行全良", "crown": "donderful", "family": "ポップス" }
+        ]
+    });
+    std::fs::write(config_dir.join("config.json"), config.to_string()).unwrap();
+
+    let proxy = TestProxy::start_with_data_dir(&wahlap, RuleSet::default(), &config_dir).await;
+    let (status, _) = proxy.request(Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut state = GoalState::load(&config_dir).await.unwrap();
+    let progress = state
+        .progress
+        .iter()
+        .map(|(name, x)| (name.as_str(), x.achieved, x.required, x.met_at.is_some()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        progress,
+        [
+            ("夏祭金雅", 1, 1, true),
+            ("流行全良", 0, 1, false),
+            ("魔王十星全连", 1, 1, true)
+        ]
+    );
+
+    // 已经达成的目标不会重复提示
+    let config_goals = crate::config::Config::load(&config_dir)
+        .await
+        .unwrap()
+        .goals;
+    let records = History::new(&config_dir).load_all().await.unwrap();
+    let newly_met = state.update(&config_goals, &records[0].scores, None, 0);
+    assert!(newly_met.is_empty());
+
+    let invalid = json!({ "goals": [{ "name": "白金", "rank": "白金" }] });
+    std::fs::write(config_dir.join("config.json"), invalid.to_string()).unwrap();
+    assert!(crate::config::Config::load(&config_dir).await.is_err());
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[test]
+fn song_star_levels_are_parsed_leniently() {
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let [saitama, natsu] = &response.data.score_info[..] else {
+        panic!("分数数据的数量不对");
+    };
+    assert_eq!(saitama.stars(), Some(10));
+    assert_eq!(saitama.song_detail.level_5, None);
+    assert_eq!(natsu.stars(), Some(6));
+    assert_eq!(natsu.song_detail.stars(5), Some(8));
+
+    let detail: crate::songs_score::SongDetail = serde_json::from_value(json!({
+        "level_1": "3",
+        "level_2": null,
+        "level_3": 7.0,
+        "level_4": "x",
+        "level_5": 0
+    }))
+    .unwrap();
+    assert_eq!(detail.level_1, Some(3));
+    assert_eq!(detail.level_2, None);
+    assert_eq!(detail.level_3, Some(7));
+    assert_eq!(detail.level_4, None);
+    assert_eq!(detail.level_5, None);
+    assert_eq!(detail.stars(6), None);
+}
+
+#[tokio::test]
+async fn song_catalog_merges_and_searches_captures() {
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let mut data = response.data;
+
+    let mut catalog = Catalog::default();
+    assert_eq!(catalog.merge(&data, 100), [1, 2]);
+
+    data.score_info[1].song_detail.song_name = "夏祭り（新）".to_string();
+    assert!(catalog.merge(&data, 200).is_empty());
+
+    let names = |songs: Vec<(i64, &crate::catalog::CatalogSong)>| {
+        songs
+            .into_iter()
+            .map(|(song_no, song)| (song_no, song.detail.song_name.clone()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(catalog.search("埼玉")), [(1, "埼玉2000".to_string())]);
+    assert_eq!(
+        names(catalog.search("さいたま")),
+        [(1, "埼玉2000".to_string())]
+    );
+    assert_eq!(
+        names(catalog.search("新")),
+        [(2, "夏祭り（新）".to_string())]
+    );
+    assert_eq!(names(catalog.opened_since("2021-01-01")).len(), 1);
+    assert_eq!(names(catalog.opened_since("2000-01-01"))[0].0, 2);
+
+    let config_dir = temp_config_dir("catalog");
+    catalog.save(&config_dir).await.unwrap();
+    let loaded = Catalog::load(&config_dir).await.unwrap();
+    let (_, song) = loaded.search("夏祭り")[0];
+    assert_eq!((song.first_seen, song.last_seen), (100, 200));
+    assert_eq!(song.detail.level_5, Some(8));
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[test]
+fn score_fields_match_the_serialized_scores() {
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let item = &response.data.score_info[0];
+    let value = serde_json::to_value(item).unwrap();
+
+    for name in crate::songs_score::field_names() {
+        let expected = match name.as_str() {
+            "stars" => json!(10),
+            "crown" => json!("全连"),
+            name => value
+                .get(name)
+                .or_else(|| value["song_detail"].get(name))
+                .cloned()
+                .unwrap(),
+        };
+        assert_eq!(item.field(&name), Some(expected), "{name}");
+    }
+    assert_eq!(item.field("song_detail.family"), item.field("family"));
+    assert_eq!(item.field("song_detail"), None);
+    assert_eq!(item.field("bogus"), None);
+}
+
+#[test]
+fn stats_count_crowns_ranks_and_accuracy() {
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let stats = crate::stats::Stats::new(&response.data, None);
+
+    let crowns = |played, clear, full_combo, donderful| crate::stats::CrownCounts {
+        played,
+        clear,
+        full_combo,
+        donderful,
+    };
+    assert_eq!(stats.by_difficulty[&3], crowns(1, 1, 0, 0));
+    assert_eq!(stats.by_difficulty[&4], crowns(1, 1, 1, 0));
+    assert_eq!(stats.by_stars[&10], crowns(1, 1, 1, 0));
+    assert_eq!(stats.by_stars[&6], crowns(1, 1, 0, 0));
+    assert_eq!(stats.ranks, [(5, 1), (8, 1)].into());
+
+    assert_eq!(
+        (stats.accuracy.good, stats.accuracy.ok, stats.accuracy.ng),
+        (991, 100, 9)
+    );
+    assert!((stats.accuracy.good_rate - 991.0 / 1100.0).abs() < 1e-9);
+    assert_eq!(stats.drumroll_hits, 49);
+    assert_eq!(
+        stats.plays_by_genre,
+        [
+            ("ナムコオリジナル".to_string(), 15),
+            ("ポップス".to_string(), 3)
+        ]
+        .into()
+    );
+    assert_eq!(stats.summary(), "魔王 通关 1/全连 1/全良 0，良率 90.1%");
+}
+
+#[test]
+fn rating_uses_best_charts_and_catalog_names() {
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let formula = crate::rating::formula("V1").unwrap();
+    assert!(crate::rating::formula("v0").is_none());
+
+    let mut renamed = response.data.clone();
+    renamed.score_info[0].song_detail.song_name = "埼玉2000（目录）".to_string();
+    let mut catalog = Catalog::default();
+    catalog.merge(&renamed, 0);
+
+    let report = crate::rating::rate(formula, &response.data, Some(&catalog));
+    assert_eq!(report.formula, "v1");
+    let contributions = report
+        .contributions
+        .iter()
+        .map(|x| (x.song_no, x.song_name.as_str(), x.rating, x.counted))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        contributions,
+        [
+            (1, "埼玉2000（目录）", 104.37, true),
+            (2, "夏祭", 49.26, true)
+        ]
+    );
+    assert_eq!(report.rating, 5.12);
+
+    // 未通关的谱面不计入评分
+    let mut failed = response.data;
+    failed.score_info[1].clear_cnt = 0;
+    let report = crate::rating::rate(formula, &failed, None);
+    assert_eq!(report.contributions.len(), 1);
+    assert_eq!(report.contributions[0].song_name, "埼玉2000");
+}
+
+#[test]
+fn song_details_are_read_from_the_catalog() {
+    use clap::Parser;
+
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let mut catalog = Catalog::default();
+    catalog.merge(&response.data, 0);
+
+    // 从同步接口导入的成绩没有歌曲信息
+    let mut record = crate::history::CaptureRecord {
+        captured_at: 1730549731,
+        scores: response.data,
+        partial: false,
+    };
+    for item in &mut record.scores.score_info {
+        item.song_detail = Default::default();
+    }
+    let catalog = Some(&catalog);
+
+    let stats = crate::stats::Stats::new(&record.scores, catalog);
+    assert_eq!(stats.by_stars.keys().copied().collect::<Vec<_>>(), [6, 10]);
+    assert!(!stats.plays_by_genre.contains_key("其他"));
+    assert!(
+        crate::stats::Stats::new(&record.scores, None)
+            .by_stars
+            .is_empty()
+    );
+
+    let rating = crate::rating::rate(
+        crate::rating::formula("v1").unwrap(),
+        &record.scores,
+        catalog,
+    );
+    assert_eq!(rating.contributions[0].song_name, "埼玉2000");
+    assert_eq!(
+        crate::targets::player_stars(&record.scores, catalog),
+        Some(10)
+    );
+
+    let cli = crate::cli::Cli::try_parse_from([
+        "taiko-score-getter",
+        "scores",
+        "query",
+        "--title",
+        "さいたま",
+        "--stars",
+        "10",
+    ])
+    .unwrap();
+    let Some(crate::cli::Command::Scores {
+        command: crate::cli::ScoresCommand::Query(query),
+    }) = cli.command
+    else {
+        panic!("没有解析出 scores query 命令");
+    };
+    let items = query.apply(&record, catalog).unwrap();
+    assert_eq!(items.len(), 1);
+    let csv = crate::query::to_csv(&record, &items, catalog);
+    assert!(
+        csv.lines()
+            .nth(1)
+            .unwrap()
+            .ends_with(",埼玉2000,さいたま2000,ナムコオリジナル,10,1")
+    );
+}
+
+#[test]
+fn next_targets_are_ranked_by_distance() {
+    use crate::targets::{self, TargetKind};
+
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let mut data = response.data;
+    data.score_info[0].ok_cnt = 3;
+    data.score_info[1].high_score = 885000;
+    assert_eq!(targets::player_stars(&data, None), Some(10));
+
+    let found = targets::next_targets(&data, None);
+    let kinds = found
+        .iter()
+        .map(|x| (x.song_no, x.kind.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        [
+            (1, TargetKind::Donderful { ok: 3 }),
+            (
+                2,
+                TargetKind::Rank {
+                    rank: 6,
+                    points: 15000
+                }
+            ),
+            (2, TargetKind::FullCombo { ng: 9 }),
+        ]
+    );
+    assert_eq!(
+        found[1].to_string(),
+        "夏祭（困难 ★6）：再得 15000 分即可达到桃雅"
+    );
+
+    // 超过玩家星级的谱面不推荐全连
+    data.score_info[1].song_detail.level_3 = Some(11);
+    let found = targets::next_targets(&data, None);
+    assert!(
+        found
+            .iter()
+            .all(|x| !matches!(x.kind, TargetKind::FullCombo { .. }))
+    );
+}
+
+#[test]
+fn score_query_filters_sorts_and_exports() {
+    use clap::Parser;
+
+    use crate::cli::{Cli, Command, ScoresCommand};
+
+    let query = |args: &[&str]| {
+        let cli = Cli::try_parse_from(["taiko-score-getter", "scores", "query"].iter().chain(args))
+            .unwrap();
+        let Some(Command::Scores {
+            command: ScoresCommand::Query(query),
+        }) = cli.command
+        else {
+            panic!("没有解析出 scores query 命令");
+        };
+        query
+    };
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let mut record = crate::history::CaptureRecord {
+        captured_at: 1730549731,
+        scores: response.data,
+        partial: false,
+    };
+    record.scores.score_info[1].song_detail.song_name = "夏祭, \"祭\"".to_string();
+    let song_nos = |args: &[&str]| {
+        query(args)
+            .apply(&record, None)
+            .unwrap()
+            .iter()
+            .map(|x| x.song_no)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(song_nos(&["--level", "魔王"]), [1]);
+    assert_eq!(song_nos(&["--stars", "..6"]), [2]);
+    assert_eq!(song_nos(&["--crown", "full-combo"]), [1]);
+    assert_eq!(song_nos(&["--rank", "金雅"]), [1, 2]);
+    assert_eq!(song_nos(&["--score", "900000.."]), [1]);
+    assert_eq!(song_nos(&["--title", "さいたま"]), [1]);
+    assert_eq!(song_nos(&["--family", "ポップ"]), [2]);
+    assert_eq!(song_nos(&["--until", "2024-10-31"]), [2]);
+    assert_eq!(song_nos(&["--sort", "ng_cnt", "--desc"]), [2, 1]);
+    assert_eq!(song_nos(&["--sort", "song_name_jp", "--limit", "1"]), [1]);
+    assert!(query(&["--sort", "bogus"]).apply(&record, None).is_err());
+    assert!(
+        Cli::try_parse_from(["taiko-score-getter", "scores", "query", "--level", "6"]).is_err()
+    );
+
+    let records = [record.clone()];
+    assert!(query(&["--at", "2024-11-01"]).select(&records).is_none());
+    assert!(query(&["--at", "2024-11-02"]).select(&records).is_some());
+    assert_eq!(song_nos(&["--since", "2024-11-2"]), [1]);
+    for date in ["2024-13-01", "yesterday", "2024-11-02 12:00:00"] {
+        for arg in ["--at", "--since", "--until"] {
+            assert!(
+                Cli::try_parse_from(["taiko-score-getter", "scores", "query", arg, date]).is_err(),
+                "{arg} {date}"
+            );
+        }
+        assert!(
+            Cli::try_parse_from(["taiko-score-getter", "songs", "new", "--since", date]).is_err(),
+            "songs new --since {date}"
+        );
+    }
+
+    let items = query(&["--sort", "song_no"]).apply(&record, None).unwrap();
+    let csv = crate::query::to_csv(&record, &items, None);
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("userid,captured_at,song_no,level,high_score"));
+    assert!(lines[2].contains(r#","夏祭, ""祭""",夏祭り,"#));
+}
+
+#[test]
+fn score_card_renders_png_and_svg_without_system_fonts() {
+    use crate::card::{Card, CardFormat};
+
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let mut record = crate::history::CaptureRecord {
+        captured_at: 1730549731,
+        scores: response.data,
+        partial: false,
+    };
+    record.scores.score_info[1].song_detail.song_name = "夏祭".repeat(40);
+    let report = crate::rating::rate(crate::rating::default_formula(), &record.scores, None);
+    let card = Card::new(&record, &report);
+
+    assert_eq!(
+        CardFormat::from_path(std::path::Path::new("card.PNG")),
+        Some(CardFormat::Png)
+    );
+    assert_eq!(
+        CardFormat::from_path(std::path::Path::new("card.jpg")),
+        None
+    );
+
+    let png = card.encode(CardFormat::Png).unwrap();
+    let decoder = png::Decoder::new(png.as_slice());
+    let reader = decoder.read_info().unwrap();
+    assert_eq!((reader.info().width, reader.info().height), (1000, 296));
+
+    let svg = card.to_svg();
+    assert!(svg.starts_with("<svg "));
+    assert!(svg.contains(r#"aria-label="埼玉2000""#));
+    assert!(svg.contains(r#"aria-label="104.37""#));
+    assert!(svg.contains("夏祭夏祭…"));
+    assert!(!svg.contains("<text"));
+}
+
+#[test]
+fn progress_report_is_self_contained() {
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let mut earlier = crate::history::CaptureRecord {
+        captured_at: 1729944000,
+        scores: response.data.clone(),
+        partial: false,
+    };
+    earlier.scores.score_info[0].high_score -= 2340;
+    earlier.scores.score_info.truncate(1);
+    let mut other_player = earlier.clone();
+    other_player.scores.userid = "1000999999".to_string();
+    other_player.scores.score_info[0].song_detail.song_name = "<别人的歌>".to_string();
+    let latest = crate::history::CaptureRecord {
+        captured_at: 1730549731,
+        scores: response.data,
+        partial: false,
+    };
+
+    let html = crate::report::render(
+        &[earlier, other_player, latest],
+        None,
+        crate::rating::default_formula(),
+    );
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("2 次捕获"));
+    assert!(html.contains("+2340"));
+    assert!(html.contains("新谱面"));
+    assert!(html.contains("<input id=\"search\""));
+    assert!(!html.contains("别人的歌"));
+    assert!(!html.contains("src="));
+    assert!(!html.contains("href="));
+}
+
+#[tokio::test]
+async fn scores_are_imported_from_raw_and_exported_files() {
+    use crate::import::{self, ImportError};
+
+    let parse = |data: &[u8], userid| {
+        import::parse(data, userid).map(|x| x.into_iter().map(|x| x.record).collect::<Vec<_>>())
+    };
+    let raw = parse(SONGSCORE_FIXTURE.as_bytes(), Some("1000999999")).unwrap();
+    assert_eq!(raw.len(), 1);
+    let record = &raw[0];
+    assert_eq!(record.scores.userid, "1000123456");
+    assert_eq!(
+        Some(record.captured_at),
+        crate::history::parse_timestamp("2024-11-02 20:15:31")
+    );
+
+    let rows = expected_rows().to_string();
+    assert!(matches!(
+        parse(rows.as_bytes(), None),
+        Err(ImportError::MissingUserid)
+    ));
+    let relayed = parse(rows.as_bytes(), Some("1000123456")).unwrap();
+    assert_eq!(relayed[0].captured_at, record.captured_at);
+    assert_eq!(relayed[0].scores.score_info[1].high_score, 856120);
+    assert_eq!(
+        relayed[0].scores.score_info[1].update_datetime,
+        "2024-10-30 21:44:05"
+    );
+
+    let mut exported = record.clone();
+    exported.scores.score_info[1].song_detail.song_name = "夏祭, \"祭\"".to_string();
+    let items = exported.scores.score_info.iter().collect::<Vec<_>>();
+    let csv = crate::query::to_csv(&exported, &items, None);
+    let from_csv = parse(csv.as_bytes(), None).unwrap();
+    assert_eq!(from_csv.len(), 1);
+    assert_eq!(from_csv[0].captured_at, exported.captured_at);
+    assert!(!from_csv[0].partial);
+    let item = &from_csv[0].scores.score_info[1];
+    assert_eq!(item.song_detail.song_name, "夏祭, \"祭\"");
+    assert_eq!(item.stars(), exported.scores.score_info[1].stars());
+    assert_eq!(item.ng_cnt, 9);
+
+    let json = serde_json::to_vec(&exported).unwrap();
+    let from_json = parse(&json, None).unwrap();
+    assert_eq!(
+        from_json[0].scores.score_info[1].song_detail.song_name,
+        "夏祭, \"祭\""
+    );
+    assert!(matches!(
+        parse(b"{\"foo\": 1}", None),
+        Err(ImportError::UnknownFormat)
+    ));
+
+    let history = History::new(&temp_config_dir("import"));
+    assert!(history.save_new(&from_csv[0]).await.unwrap().is_some());
+    assert!(history.save_new(&from_json[0]).await.unwrap().is_none());
+    assert_eq!(history.load_all().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn imports_update_the_catalog_goals_and_partial_exports() {
+    use crate::import::{self, ImportError};
+
+    let config_dir = temp_config_dir("import-catalog");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    let config = json!({ "goals": [{ "name": "夏祭金雅", "rank": "金雅", "song_no": 2 }] });
+    std::fs::write(config_dir.join("config.json"), config.to_string()).unwrap();
+    let import = async |data: &[u8], userid| {
+        let mut saved = Vec::new();
+        for entry in import::parse(data, userid)? {
+            saved.push(import::save(&config_dir, entry).await?);
+        }
+        Ok::<_, ImportError>(saved)
+    };
+
+    // 筛选后导出的部分成绩需要合并到更早的捕获记录中
+    let response: crate::songs_score::Response = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let mut later = crate::history::CaptureRecord {
+        captured_at: 1730600000,
+        scores: response.data,
+        partial: false,
+    };
+    later.scores.score_info[1].high_score = 900000;
+    let items = [&later.scores.score_info[1]];
+    let csv = crate::query::to_csv(&later, &items, None);
+    assert!(csv.lines().nth(1).unwrap().ends_with(",1"));
+    assert!(matches!(
+        import(csv.as_bytes(), None).await,
+        Err(ImportError::MissingBaseRecord)
+    ));
+
+    // 原始数据中的歌曲信息合并到歌曲目录中
+    let saved = import(SONGSCORE_FIXTURE.as_bytes(), None).await.unwrap();
+    assert!(saved[0].is_some());
+    let catalog = Catalog::load(&config_dir).await.unwrap();
+    assert_eq!(catalog.search("").len(), 2);
+
+    let saved = import(csv.as_bytes(), None).await.unwrap();
+    let merged = saved[0].as_ref().unwrap();
+    assert!(!merged.partial);
+    assert_eq!(merged.captured_at, later.captured_at);
+    let scores = merged
+        .scores
+        .score_info
+        .iter()
+        .map(|x| (x.song_no, x.high_score, x.song_detail.open_day.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        scores,
+        [(1, 1002340, "2020-01-01"), (2, 900000, "2021-06-24")]
+    );
+
+    // 同步接口的数组没有歌曲信息，使用歌曲目录补全
+    let mut rows = expected_rows();
+    rows[0][13] = json!("2024-11-03 12:00:00");
+    let saved = import(rows.to_string().as_bytes(), Some("1000123456"))
+        .await
+        .unwrap();
+    let relayed = saved[0].as_ref().unwrap();
+    assert_eq!(
+        relayed.scores.score_info[0].song_detail.song_name,
+        "埼玉2000"
+    );
+    assert_eq!(relayed.scores.score_info[1].stars(), Some(6));
+
+    import::update_goals(&config_dir).await.unwrap();
+    let state = GoalState::load(&config_dir).await.unwrap();
+    let progress = &state.progress["夏祭金雅"];
+    assert_eq!((progress.achieved, progress.required), (1, 1));
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn fetch_score_without_capture_is_passed_through() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let proxy = TestProxy::start(&wahlap).await;
+
+    let (status, body) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body, "not found");
+}
+
+#[tokio::test]
+async fn failed_songscore_status_is_not_relayed() {
+    let fixture = r#"{"status":401,"message":"未登录","requestId":"","data":{}}"#;
+    let wahlap = MockWahlap::start(fixture).await;
+    let proxy = TestProxy::start(&wahlap).await;
+
+    let (status, body) = proxy.request(Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, fixture);
+
+    let (status, _) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn compressed_songscore_is_captured_and_forwarded_unchanged() {
+    let wahlap = MockWahlap::start_with(SONGSCORE_FIXTURE, SongScoreBody::Gzip).await;
+    let proxy = TestProxy::start(&wahlap).await;
+
+    let (status, body) = proxy.request(Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, mock::gzip(SONGSCORE_FIXTURE.as_bytes()));
+
+    let (status, body) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(rows, expected_rows());
+}
+
+#[tokio::test]
+async fn oversized_songscore_is_streamed_unchanged() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let limits = CaptureLimits {
+        max_body_size: 64,
+        ..Default::default()
+    };
+    let proxy = TestProxy::start_with_limits(&wahlap, limits).await;
+
+    let (status, body) = proxy.request(Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, SONGSCORE_FIXTURE.as_bytes());
+
+    let (status, _) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn stalled_songscore_times_out_but_still_arrives() {
+    let body = SongScoreBody::Stalled(Duration::from_millis(500));
+    let wahlap = MockWahlap::start_with(SONGSCORE_FIXTURE, body).await;
+    let limits = CaptureLimits {
+        timeout: Duration::from_millis(100),
+        ..Default::default()
+    };
+    let proxy = TestProxy::start_with_limits(&wahlap, limits).await;
+
+    let (status, body) = proxy.request(Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, SONGSCORE_FIXTURE.as_bytes());
+
+    let (status, _) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn concurrent_requests_are_correlated() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let proxy = Arc::new(TestProxy::start(&wahlap).await);
+
+    let mut tasks = Vec::new();
+    for i in 0..16 {
+        let proxy = proxy.clone();
+        tasks.push(tokio::spawn(async move {
+            if i % 2 == 0 {
+                let (status, body) = proxy.request(Method::POST, SONGSCORE_URL).await;
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(body, SONGSCORE_FIXTURE.as_bytes());
+            } else {
+                let (status, body) = proxy.request(Method::GET, OTHER_WAHLAP_URL).await;
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(body, "not found");
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let mut tasks = Vec::new();
+    for _ in 0..4 {
+        let proxy = proxy.clone();
+        tasks.push(tokio::spawn(async move {
+            proxy.request(Method::GET, FETCH_SCORE_URL).await
+        }));
+    }
+    for task in tasks {
+        let (status, body) = task.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(rows, expected_rows());
+    }
+}
+
+#[tokio::test]
+async fn multiplexed_http2_requests_are_correlated() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let proxy = TestProxy::start(&wahlap).await;
+
+    let requests: Vec<_> = (0..8)
+        .map(|i| {
+            if i % 2 == 0 {
+                (Method::POST, SONGSCORE_URL)
+            } else {
+                (Method::GET, OTHER_WAHLAP_URL)
+            }
+        })
+        .collect();
+    let responses = proxy.request_h2(&requests).await;
+    for ((method, _), (status, body)) in requests.iter().zip(responses) {
+        if method == Method::POST {
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body, SONGSCORE_FIXTURE.as_bytes());
+        } else {
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body, "not found");
+        }
+    }
+
+    let responses = proxy
+        .request_h2(&[
+            (Method::GET, FETCH_SCORE_URL),
+            (Method::GET, FETCH_SCORE_URL),
+        ])
+        .await;
+    for (status, body) in responses {
+        assert_eq!(status, StatusCode::OK);
+        let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(rows, expected_rows());
+    }
+}
+
+#[tokio::test]
+async fn other_hosts_are_tunneled_without_decryption() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let proxy = TestProxy::start(&wahlap).await;
+
+    // 客户端只信任模拟服务器的证书，如果代理解密了连接，握手就会失败
+    let stream = proxy.connect("127.0.0.1", wahlap.addr().port()).await;
+    let (status, body) =
+        send_https(&wahlap.tls_connector(), stream, Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, SONGSCORE_FIXTURE.as_bytes());
+
+    // 隧道中的流量不会被捕获
+    let (status, _) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn intercepted_hosts_match_exactly() {
+    let rules = RuleSet::default();
+    assert!(rules.intercepts("wl-taiko.wahlap.net"));
+    assert!(rules.intercepts("WL-Taiko.Wahlap.Net."));
+    assert!(!rules.intercepts("wahlap.net"));
+    assert!(!rules.intercepts("evil-wl-taiko.wahlap.net"));
+    assert!(!rules.intercepts("127.0.0.1"));
+}
+
+#[test]
+fn capture_rule_paths_use_globs() {
+    let glob = Glob::from("/api/*/songscore".to_string());
+    assert!(glob.matches("/api/user/profile/songscore"));
+    assert!(glob.matches("/api//songscore"));
+    assert!(!glob.matches("/api/user/profile/songscore/other"));
+    assert!(!glob.matches("/v2/api/user/songscore"));
+
+    let glob = Glob::from("/api/v?/*".to_string());
+    assert!(glob.matches("/api/v2/songscore"));
+    assert!(!glob.matches("/api/v10/songscore"));
+    assert!(Glob::default().matches("/"));
+}
+
+#[test]
+fn extract_supports_pointers_and_paths() {
+    let value: serde_json::Value = serde_json::from_str(SONGSCORE_FIXTURE).unwrap();
+    let extract = |source: &str| Extract::try_from(source.to_string()).unwrap();
+
+    assert_eq!(
+        extract("/data/scoreInfo/1/song_no").apply(&value),
+        Some(json!(2))
+    );
+    assert_eq!(
+        extract("data.scoreInfo[0].level").apply(&value),
+        Some(json!(4))
+    );
+    assert_eq!(
+        extract("data.scoreInfo[*].song_no").apply(&value),
+        Some(json!([1, 2]))
+    );
+    assert_eq!(extract("data.missing").apply(&value), None);
+    assert_eq!(extract("").apply(&value), Some(value.clone()));
+
+    assert!(Extract::try_from("data..userid".to_string()).is_err());
+    assert!(Extract::try_from("data.scoreInfo[x]".to_string()).is_err());
+}
+
+#[tokio::test]
+async fn relay_templates_shape_fetch_score_responses() {
+    use crate::rules::{CaptureRule, RelayTemplate};
+
+    let fork: CaptureRule = serde_json::from_value(json!({
+        "name": "分支同步接口",
+        "host": "www.baidu.com",
+        "path": "/api/fork",
+        "handler": "respond",
+        "slot": "score",
+        "template": {
+            "version": "fork-v2",
+            "row": "object",
+            "wrap": "scores",
+            "columns": [
+                "song_no",
+                {"field": "level", "name": "difficulty", "transform": "string"},
+                {"field": "song_name_jp", "name": "title"},
+                "stars",
+                "crown",
+                {"field": "update_datetime", "name": "played_on", "transform": "date"},
+                {"field": "highscore_datetime", "name": "time", "transform": "timestamp"},
+                {"field": "full_combo_cnt", "name": "full_combo", "transform": "flag"}
+            ]
+        }
+    }))
+    .unwrap();
+    let mut rules = crate::rules::default_rules();
+    rules.insert(1, fork);
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let proxy = TestProxy::start_with_rules(&wahlap, RuleSet::new(rules).unwrap()).await;
+
+    proxy.request(Method::POST, SONGSCORE_URL).await;
+    let res = proxy
+        .request_response(Method::GET, "https://www.baidu.com/api/fork")
+        .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[crate::RELAY_VERSION_HEADER], "fork-v2");
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(res.body()).unwrap()["scores"][0],
+        json!({
+            "song_no": 1,
+            "difficulty": "4",
+            "title": "さいたま2000",
+            "stars": 10,
+            "crown": "全连",
+            "played_on": "2024-11-02",
+            "time": 1730549731,
+            "full_combo": true
+        })
+    );
+    // 同一次捕获仍然可以按内置格式返回给原版的接收网站
+    let res = proxy.request_response(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(res.headers()[crate::RELAY_VERSION_HEADER], "v1");
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(res.body()).unwrap(),
+        expected_rows()
+    );
+
+    let template = |value| serde_json::from_value::<RelayTemplate>(value);
+    assert_eq!(template(json!("v1")).unwrap(), RelayTemplate::default());
+    assert!(template(json!("v9")).is_err());
+    assert!(template(json!({"version": "v1", "columns": ["song_no"]})).is_err());
+    assert!(template(json!({"version": "x", "columns": []})).is_err());
+    assert!(template(json!({"version": "x", "columns": ["bogus"]})).is_err());
+    assert!(
+        template(json!({"version": "x", "row": "object", "columns": ["level", "level"]})).is_err()
+    );
+}
+
+#[tokio::test]
+async fn relay_targets_replace_the_default_sync_endpoint() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let proxy = TestProxy::start(&wahlap).await;
+    proxy.request(Method::POST, SONGSCORE_URL).await;
+
+    // 同步接口以外的请求正常转发，不会被分数数据代替
+    for url in [
+        "https://www.baidu.com/",
+        "https://www.baidu.com/api/ahfsdafbaqwerhue/other",
+    ] {
+        let (status, _) = proxy.request(Method::GET, url).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{url}");
+    }
+    let (status, _) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let config_dir = temp_config_dir("relay-targets");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join(crate::config::CONFIG_FILE_NAME),
+        json!({
+            "relay_targets": [
+                {"host": "www.baidu.com", "path": "/api/ahfsdafbaqwerhue", "finish": false},
+                {
+                    "host": OTHER_RELAY_HOST,
+                    "path": "/sync",
+                    "method": "POST",
+                    "template": {"version": "other-v1", "columns": ["song_no", "high_score"]}
+                }
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let rules = crate::config::load_rules(&config_dir).await.unwrap();
+    assert!(rules.intercepts(OTHER_RELAY_HOST));
+
+    let proxy = TestProxy::start_with_rules(&wahlap, rules).await;
+    proxy.request(Method::POST, SONGSCORE_URL).await;
+    let (_, body) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        expected_rows()
+    );
+    let other_url = format!("https://{OTHER_RELAY_HOST}/sync");
+    let (status, _) = proxy.request(Method::GET, &other_url).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = proxy.request(Method::POST, &other_url).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        json!([[1, 1002340], [2, 856120]])
+    );
+
+    std::fs::write(
+        config_dir.join(crate::config::CONFIG_FILE_NAME),
+        json!({"relay_targets": [{"host": "www.baidu.com", "path": "/api/*"}]}).to_string(),
+    )
+    .unwrap();
+    assert!(crate::config::load_rules(&config_dir).await.is_err());
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn custom_rules_are_loaded_from_config() {
+    let config_dir = temp_config_dir("custom-rules");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(
+        config_dir.join(crate::config::CONFIG_FILE_NAME),
+        json!({
+            "rules": [
+                {
+                    "name": "歌曲列表",
+                    "host": "WL-Taiko.Wahlap.Net",
+                    "path": "/api/*/songscore",
+                    "method": "post",
+                    "content_type": "application/json",
+                    "extract": "data.scoreInfo[*].song_no",
+                    "handler": "store",
+                    "slot": "songs"
+                },
+                {
+                    "name": "歌曲同步",
+                    "host": "www.baidu.com",
+                    "path": "/api/songs",
+                    "handler": "respond",
+                    "slot": "songs"
+                }
+            ]
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let rules = crate::config::load_rules(&config_dir).await.unwrap();
+    assert_eq!(rules.hosts(), default_hosts());
+
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let proxy = TestProxy::start_with_rules(&wahlap, rules).await;
+
+    let (status, body) = proxy.request(Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, SONGSCORE_FIXTURE.as_bytes());
+
+    // 只有配置的路径会返回保存的数据
+    let (status, _) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = proxy
+        .request(Method::GET, "https://www.baidu.com/api/songs")
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        json!([1, 2])
+    );
+
+    std::fs::write(
+        config_dir.join(crate::config::CONFIG_FILE_NAME),
+        r#"{"rules": []}"#,
+    )
+    .unwrap();
+    assert!(crate::config::load_rules(&config_dir).await.is_err());
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn intercepted_requests_use_upstream_proxy() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let upstream = MockUpstream::start(false, wahlap.addr()).await;
+    let proxy = TestProxy::start_with_upstream(&wahlap, &upstream).await;
+
+    let (status, body) = proxy.request(Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, SONGSCORE_FIXTURE.as_bytes());
+    assert_eq!(upstream.requested(), ["wl-taiko.wahlap.net:443"]);
+}
+
+#[tokio::test]
+async fn tunneled_connections_use_upstream_proxy() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let upstream = MockUpstream::start(true, wahlap.addr()).await;
+    let proxy = TestProxy::start_with_upstream(&wahlap, &upstream).await;
+
+    let stream = proxy.connect("example.com", 443).await;
+    let (status, body) =
+        send_https(&wahlap.tls_connector(), stream, Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, SONGSCORE_FIXTURE.as_bytes());
+    assert_eq!(upstream.requested(), ["example.com:443"]);
+}
+
+#[tokio::test]
+async fn socks5_connections_share_the_interception_pipeline() {
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let proxy = TestProxy::start(&wahlap).await;
+
+    let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let socks5 = crate::upstream::UpstreamProxy::Socks5 {
+        host: Ipv4Addr::LOCALHOST.to_string(),
+        port: listener.local_addr().unwrap().port(),
+    };
+    let task = tokio::spawn(crate::socks5::serve(
+        listener,
+        proxy.addr(),
+        RuleSet::default(),
+    ));
+
+    let stream = socks5.connect("wl-taiko.wahlap.net", 443).await.unwrap();
+    let (status, body) =
+        send_https(&proxy.tls_connector(), stream, Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, SONGSCORE_FIXTURE.as_bytes());
+
+    // 通过 SOCKS5 捕获到的成绩可以通过 HTTP 代理同步
+    let (status, body) = proxy.request(Method::GET, FETCH_SCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(rows, expected_rows());
+
+    // 在本地解析域名的客户端只给出 IP 地址，根据 SNI 仍然可以拦截
+    let stream = socks5.connect("127.0.0.1", 443).await.unwrap();
+    let (status, body) =
+        send_https(&proxy.tls_connector(), stream, Method::POST, SONGSCORE_URL).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, SONGSCORE_FIXTURE.as_bytes());
+
+    // 其它域名同样不会被解密
+    let stream = socks5
+        .connect("127.0.0.1", wahlap.addr().port())
+        .await
+        .unwrap();
+    let other_url = format!("https://{OTHER_RELAY_HOST}/api/user/profile/songscore");
+    let (status, _) = send_https(&wahlap.tls_connector(), stream, Method::POST, &other_url).await;
+    assert_eq!(status, StatusCode::OK);
+
+    task.abort();
+}
+
+#[test]
+fn upstream_proxy_is_parsed_from_url() {
+    use crate::upstream::UpstreamProxy;
+
+    let http = UpstreamProxy::Http {
+        host: "127.0.0.1".to_string(),
+        port: 7890,
+    };
+    assert_eq!("127.0.0.1:7890".parse(), Ok(http.clone()));
+    assert_eq!("http://127.0.0.1:7890/".parse(), Ok(http.clone()));
+    assert_eq!(
+        "SOCKS5://[::1]:7891".parse(),
+        Ok(UpstreamProxy::Socks5 {
+            host: "::1".to_string(),
+            port: 7891
+        })
+    );
+    assert!("https://127.0.0.1:7890".parse::<UpstreamProxy>().is_err());
+    assert!("127.0.0.1".parse::<UpstreamProxy>().is_err());
+
+    // 程序异常退出后残留的系统代理指向自身，不能作为上游代理
+    let listen_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 7650));
+    assert!(!crate::is_own_address(&http, listen_addr));
+    assert!(crate::is_own_address(
+        &"localhost:7650".parse().unwrap(),
+        listen_addr
+    ));
+}
+
+#[tokio::test]
+async fn get_ca_installs_generated_cert_once() {
+    let config_dir = temp_config_dir("get-ca");
+    let cert_store = FakeCertStore::default();
+
+    crate::os::get_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    assert!(config_dir.join("ca.cer").exists());
+    assert!(config_dir.join("ca.key").exists());
+    let installed = cert_store.installed();
+    assert_eq!(installed.len(), 1);
+    assert!(installed[0].starts_with(crate::os::CERT_NAME_PREFIX));
+    assert_ne!(installed[0], crate::os::CERT_NAME_PREFIX);
+
+    crate::os::get_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    assert_eq!(cert_store.install_count(), 1, "已安装的证书不应重复安装");
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[test]
+fn generated_ca_is_bounded_and_name_constrained() {
+    let (cert, _) = crate::os::generate_ca(&default_hosts());
+    let params = CertificateParams::from_ca_cert_pem(&cert.pem()).unwrap();
+
+    assert!(matches!(
+        params.is_ca,
+        IsCa::Ca(BasicConstraints::Constrained(0))
+    ));
+    let validity = params.not_after - params.not_before;
+    assert!(validity <= time::Duration::days(731));
+
+    let permitted: Vec<_> = params
+        .name_constraints
+        .unwrap()
+        .permitted_subtrees
+        .into_iter()
+        .map(|subtree| match subtree {
+            GeneralSubtree::DnsName(name) => name,
+            other => panic!("意外的名称约束 {other:?}"),
+        })
+        .collect();
+    assert_eq!(permitted, default_hosts());
+
+    let (other, _) = crate::os::generate_ca(&default_hosts());
+    assert_ne!(
+        crate::os::cert_name(cert.params()),
+        crate::os::cert_name(other.params()),
+        "每次生成的证书名称都应该不同"
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn generated_key_file_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let config_dir = temp_config_dir("key-permissions");
+    crate::os::get_ca(&config_dir, &default_hosts(), &FakeCertStore::default())
+        .await
+        .unwrap();
+
+    let key_path = config_dir.join("ca.key");
+    let mode = || std::fs::metadata(&key_path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(), 0o600);
+
+    // 检查证书时不修改文件，旧版本写入的私钥文件在下次启动时收紧
+    std::fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    crate::os::check_ca(&config_dir, &default_hosts(), &FakeCertStore::default()).await;
+    assert_eq!(mode(), 0o644);
+    crate::os::get_ca(&config_dir, &default_hosts(), &FakeCertStore::default())
+        .await
+        .unwrap();
+    assert_eq!(mode(), 0o600);
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[test]
+fn encrypted_key_round_trip() {
+    let (_, key_pair) = crate::os::generate_ca(&default_hosts());
+    let pem = key_pair.serialize_pem();
+
+    let encrypted = key_file::encrypt(&pem, "どんだふる");
+    assert!(key_file::is_encrypted(&encrypted));
+    assert!(!encrypted.contains(&pem));
+    assert_eq!(key_file::decrypt(&encrypted, "どんだふる"), Some(pem));
+    assert_eq!(key_file::decrypt(&encrypted, "wrong"), None);
+}
+
+#[tokio::test]
+async fn locked_key_stops_proxy_before_changing_system_proxy() {
+    let config_dir = temp_config_dir("locked-key");
+    let cert_store = FakeCertStore::default();
+    let system_proxy = FakeProxy::default();
+    crate::os::get_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    let key_path = config_dir.join("ca.key");
+    let pem = std::fs::read_to_string(&key_path).unwrap();
+    std::fs::write(&key_path, key_file::encrypt(&pem, "どんだふる")).unwrap();
+
+    let (sx, _finished_rx) = tokio::sync::mpsc::channel(1);
+    let (_close_sx, close_rx) = tokio::sync::mpsc::channel(1);
+    let result = crate::proxy_main(
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        &config_dir,
+        &cert_store,
+        &system_proxy,
+        crate::ProxyOptions::default(),
+        sx,
+        close_rx,
+    )
+    .await;
+
+    assert!(matches!(result, Err(CaProblem::KeyLocked)));
+    assert_eq!(system_proxy.current(), None);
+    assert_eq!(system_proxy.recover_count(), 0);
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn rotate_ca_replaces_installed_certs() {
+    let config_dir = temp_config_dir("rotate");
+    let cert_store = FakeCertStore::default();
+
+    crate::os::get_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    cert_store
+        .install_cert(crate::os::CERT_NAME_PREFIX, &config_dir.join("ca.cer"))
+        .await;
+    let old_cert = std::fs::read_to_string(config_dir.join("ca.cer")).unwrap();
+    let mut old_names = cert_store.installed();
+
+    let report = crate::os::rotate_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    old_names.sort();
+    let mut removed = report.removed_certs.clone();
+    removed.sort();
+    assert_eq!(removed, old_names);
+    assert_eq!(
+        cert_store.installed(),
+        std::slice::from_ref(&report.cert_name)
+    );
+    assert_ne!(
+        std::fs::read_to_string(config_dir.join("ca.cer")).unwrap(),
+        old_cert
+    );
+    assert!(!config_dir.join("ca.cer.new").exists());
+    assert!(!config_dir.join("ca.key.new").exists());
+
+    let status = crate::os::check_ca(&config_dir, &default_hosts(), &cert_store).await;
+    assert_eq!(status.cert_name, Some(report.cert_name));
+    assert!(status.problems.is_empty(), "{:?}", status.problems);
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn interrupted_rotation_is_recovered_on_start() {
+    let config_dir = temp_config_dir("rotate-interrupted");
+    let cert_store = FakeCertStore::default();
+    crate::os::get_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    let cert = std::fs::read_to_string(config_dir.join("ca.cer")).unwrap();
+
+    // 证书文件替换之前中断，放弃这次更换
+    let (new_cert, new_key) = crate::os::generate_ca(&default_hosts());
+    std::fs::write(config_dir.join("ca.cer.new"), new_cert.pem()).unwrap();
+    std::fs::write(config_dir.join("ca.key.new"), new_key.serialize_pem()).unwrap();
+    crate::os::get_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    assert!(!config_dir.join("ca.cer.new").exists());
+    assert!(!config_dir.join("ca.key.new").exists());
+    assert_eq!(
+        std::fs::read_to_string(config_dir.join("ca.cer")).unwrap(),
+        cert
+    );
+
+    // 证书文件已经替换，继续替换私钥文件
+    std::fs::write(config_dir.join("ca.cer"), new_cert.pem()).unwrap();
+    std::fs::write(config_dir.join("ca.key.new"), new_key.serialize_pem()).unwrap();
+    crate::os::get_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    assert!(!config_dir.join("ca.key.new").exists());
+    assert_eq!(
+        std::fs::read_to_string(config_dir.join("ca.cer")).unwrap(),
+        new_cert.pem()
+    );
+    let status = crate::os::check_ca(&config_dir, &default_hosts(), &cert_store).await;
+    assert!(status.problems.is_empty(), "{:?}", status.problems);
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn expired_legacy_ca_is_rotated_on_start() {
+    let config_dir = temp_config_dir("expired");
+    let cert_store = FakeCertStore::default();
+
+    // 旧版本生成的证书没有名称约束和后缀，这里再让它过期
+    let mut params = CertificateParams::default();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, crate::os::CERT_NAME_PREFIX);
+    params.not_before = time::OffsetDateTime::now_utc() - time::Duration::days(400);
+    params.not_after = time::OffsetDateTime::now_utc() - time::Duration::days(1);
+    let key_pair = KeyPair::generate().unwrap();
+    let cert = params.self_signed(&key_pair).unwrap();
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(config_dir.join("ca.cer"), cert.pem()).unwrap();
+    std::fs::write(config_dir.join("ca.key"), key_pair.serialize_pem()).unwrap();
+    cert_store
+        .install_cert(crate::os::CERT_NAME_PREFIX, &config_dir.join("ca.cer"))
+        .await;
+
+    let status = crate::os::check_ca(&config_dir, &default_hosts(), &cert_store).await;
+    assert!(status.problems.contains(&CaProblem::Expired));
+    assert!(status.problems.contains(&CaProblem::ConstraintsMismatch));
+    assert!(status.needs_rotation());
+
+    crate::os::get_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    let installed = cert_store.installed();
+    assert_eq!(installed.len(), 1);
+    assert_ne!(installed[0], crate::os::CERT_NAME_PREFIX);
+
+    let status = crate::os::check_ca(&config_dir, &default_hosts(), &cert_store).await;
+    assert!(status.problems.is_empty(), "{:?}", status.problems);
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn check_ca_detects_mismatched_key() {
+    let config_dir = temp_config_dir("mismatched-key");
+    let cert_store = FakeCertStore::default();
+
+    crate::os::get_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    let (_, other_key) = crate::os::generate_ca(&default_hosts());
+    std::fs::write(config_dir.join("ca.key"), other_key.serialize_pem()).unwrap();
+
+    let status = crate::os::check_ca(&config_dir, &default_hosts(), &cert_store).await;
+    assert_eq!(status.problems, [CaProblem::KeyMismatch]);
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn proxy_main_sets_and_recovers_system_proxy() {
+    let config_dir = temp_config_dir("proxy-main");
+    let cert_store = FakeCertStore::default();
+    let system_proxy = FakeProxy::default();
+    let (sx, _finished_rx) = tokio::sync::mpsc::channel(1);
+    let (close_sx, close_rx) = tokio::sync::mpsc::channel(1);
+
+    let close = async {
+        let (host, port) = loop {
+            if let Some(current) = system_proxy.current() {
+                break current;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(host, "127.0.0.1");
+        assert_ne!(port, 0);
+        close_sx.send(()).await.unwrap();
+    };
+
+    let (result, ()) = tokio::join!(
+        crate::proxy_main(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            &config_dir,
+            &cert_store,
+            &system_proxy,
+            crate::ProxyOptions::default(),
+            sx,
+            close_rx,
+        ),
+        close
+    );
+    result.unwrap();
+
+    assert_eq!(system_proxy.current(), None);
+    assert_eq!(system_proxy.recover_count(), 1);
+    assert_eq!(cert_store.install_count(), 1);
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn proxy_main_chains_previous_system_proxy() {
+    let config_dir = temp_config_dir("proxy-main-upstream");
+    let wahlap = MockWahlap::start(SONGSCORE_FIXTURE).await;
+    let upstream = MockUpstream::start(false, wahlap.addr()).await;
+    let cert_store = FakeCertStore::default();
+    let system_proxy = FakeProxy::with_previous(upstream.proxy());
+    let (sx, _finished_rx) = tokio::sync::mpsc::channel(1);
+    let (close_sx, close_rx) = tokio::sync::mpsc::channel(1);
+
+    let close = async {
+        let (_, port) = loop {
+            if let Some(current) = system_proxy.current() {
+                break current;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        let stream = connect_via(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            "example.com",
+            443,
+        )
+        .await;
+        let (status, _) =
+            send_https(&wahlap.tls_connector(), stream, Method::POST, SONGSCORE_URL).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(upstream.requested(), ["example.com:443"]);
+
+        close_sx.send(()).await.unwrap();
+    };
+
+    let (result, ()) = tokio::join!(
+        crate::proxy_main(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            &config_dir,
+            &cert_store,
+            &system_proxy,
+            crate::ProxyOptions::default(),
+            sx,
+            close_rx,
+        ),
+        close
+    );
+    result.unwrap();
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[test]
+fn pac_script_routes_only_score_hosts() {
+    let listen_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 7650));
+
+    let pac = crate::pac::generate_pac(&default_hosts(), listen_addr, None);
+    assert!(pac.contains(r#"if (host === "wl-taiko.wahlap.net" || host === "www.baidu.com") {"#));
+    assert!(pac.contains(r#"return "PROXY 127.0.0.1:7650";"#));
+    assert!(pac.contains(r#"return "DIRECT";"#));
+
+    let upstream = "socks5://127.0.0.1:7891".parse().unwrap();
+    let pac = crate::pac::generate_pac(&default_hosts(), listen_addr, Some(&upstream));
+    assert!(pac.contains(r#"return "SOCKS5 127.0.0.1:7891; DIRECT";"#));
+}
+
+#[tokio::test]
+async fn proxy_main_serves_pac_in_pac_mode() {
+    let config_dir = temp_config_dir("proxy-main-pac");
+    let cert_store = FakeCertStore::default();
+    let system_proxy = FakeProxy::default();
+    let (sx, _finished_rx) = tokio::sync::mpsc::channel(1);
+    let (close_sx, close_rx) = tokio::sync::mpsc::channel(1);
+
+    let close = async {
+        let pac_url = loop {
+            if let Some(pac_url) = system_proxy.auto_config() {
+                break pac_url;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(system_proxy.current(), None, "PAC 模式不应设置全局代理");
+
+        let pac_url: http::Uri = pac_url.parse().unwrap();
+        let addr: SocketAddr = pac_url.authority().unwrap().as_str().parse().unwrap();
+        let res = get_direct(addr, pac_url.path()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[http::header::CONTENT_TYPE],
+            crate::pac::PAC_CONTENT_TYPE
+        );
+        let pac = String::from_utf8(res.body().to_vec()).unwrap();
+        assert!(pac.contains(&format!(r#"return "PROXY {addr}";"#)));
+
+        close_sx.send(()).await.unwrap();
+    };
+
+    let (result, ()) = tokio::join!(
+        crate::proxy_main(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            &config_dir,
+            &cert_store,
+            &system_proxy,
+            crate::ProxyOptions {
+                pac: true,
+                ..Default::default()
+            },
+            sx,
+            close_rx,
+        ),
+        close
+    );
+    result.unwrap();
+
+    assert_eq!(system_proxy.auto_config(), None);
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn uninstall_removes_cert_key_and_leftover_proxy() {
+    let config_dir = temp_config_dir("uninstall");
+    let cert_store = FakeCertStore::default();
+    let system_proxy = FakeProxy::default();
+    let listen_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 7650));
+
+    crate::os::get_ca(&config_dir, &default_hosts(), &cert_store)
+        .await
+        .unwrap();
+    // 旧版本安装的证书没有后缀
+    cert_store
+        .install_cert(crate::os::CERT_NAME_PREFIX, &config_dir.join("ca.cer"))
+        .await;
+    system_proxy.set_proxy("127.0.0.1", 7650).await;
+    // 更换证书中断时留下的新文件也需要删除
+    std::fs::write(config_dir.join("ca.key.new"), "key").unwrap();
+
+    let installed = cert_store.installed();
+    let report = crate::os::uninstall(&config_dir, listen_addr, &cert_store, &system_proxy).await;
+    assert!(report.proxy_restored);
+    assert_eq!(report.removed_certs, installed);
+    assert_eq!(
+        report.removed_files,
+        [
+            config_dir.join("ca.key"),
+            config_dir.join("ca.cer"),
+            config_dir.join("ca.key.new")
+        ]
+    );
+    assert!(report.failed_files.is_empty());
+    assert!(cert_store.installed().is_empty());
+    assert_eq!(system_proxy.current(), None);
+
+    let report = crate::os::uninstall(&config_dir, listen_addr, &cert_store, &system_proxy).await;
+    assert!(!report.proxy_restored);
+    assert!(report.removed_certs.is_empty());
+    assert!(report.removed_files.is_empty());
+
+    let _ = std::fs::remove_dir_all(&config_dir);
+}
+
+#[tokio::test]
+async fn uninstall_keeps_unrelated_proxy() {
+    let config_dir = temp_config_dir("uninstall-unrelated");
+    let system_proxy = FakeProxy::default();
+    system_proxy.set_proxy("127.0.0.1", 7890).await;
+
+    let report = crate::os::uninstall(
+        &config_dir,
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 7650)),
+        &FakeCertStore::default(),
+        &system_proxy,
+    )
+    .await;
+    assert!(!report.proxy_restored);
+    assert_eq!(
+        system_proxy.current(),
+        Some(("127.0.0.1".to_string(), 7890))
+    );
+}
+
+#[tokio::test]
+async fn uninstall_removes_leftover_auto_config() {
+    let config_dir = temp_config_dir("uninstall-pac");
+    let system_proxy = FakeProxy::default();
+    let listen_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 7650));
+    system_proxy
+        .set_auto_config(&crate::pac::pac_url(listen_addr))
+        .await;
+
+    let report = crate::os::uninstall(
+        &config_dir,
+        listen_addr,
+        &FakeCertStore::default(),
+        &system_proxy,
+    )
+    .await;
+    assert!(report.proxy_restored);
+    assert_eq!(system_proxy.auto_config(), None);
+}
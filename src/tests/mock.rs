@@ -0,0 +1,562 @@
+//! 测试用的模拟服务器和代理客户端，全程只使用本机回环地址，不会修改系统代理
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    io::Write,
+    net::{Ipv4Addr, SocketAddr},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use flate2::{Compression, write::GzEncoder};
+use http::{Method, Request, Response, StatusCode, Uri, header};
+use http_body_util::{BodyExt, Full, StreamBody, combinators::UnsyncBoxBody};
+use hudsucker::{
+    certificate_authority::RcgenAuthority,
+    futures::{StreamExt, stream},
+    hyper::body::{Bytes, Frame, Incoming},
+    rcgen::{CertificateParams, KeyPair},
+    rustls::{
+        ClientConfig, RootCertStore, ServerConfig,
+        crypto::ring::default_provider,
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName},
+    },
+};
+use hyper_util::{
+    client::legacy::{Client, connect::Connect},
+    rt::{TokioExecutor, TokioIo},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::Receiver,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::{capture::CaptureLimits, rules::RuleSet, upstream::UpstreamProxy};
+
+pub const SONGSCORE_PATH: &str = "/api/user/profile/songscore";
+
+fn client_config(root: &CertificateDer<'static>) -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+    roots.add(root.clone()).expect("无法添加测试根证书");
+
+    ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+/// 测试中额外的同步网站
+pub const OTHER_RELAY_HOST: &str = "sync.example.com";
+
+/// 模拟的 `wl-taiko.wahlap.net` 服务器，同时也会接管 `www.baidu.com` 和 [`OTHER_RELAY_HOST`] 的请求
+pub struct MockWahlap {
+    addr: SocketAddr,
+    ca_der: CertificateDer<'static>,
+}
+
+/// 模拟服务器返回分数数据的方式
+#[derive(Debug, Clone, Copy)]
+pub enum SongScoreBody {
+    Plain,
+    /// 使用 gzip 压缩
+    Gzip,
+    /// 先返回一半数据，等待一段时间后再返回剩余数据
+    Stalled(Duration),
+}
+
+impl MockWahlap {
+    pub async fn start(songscore: &'static str) -> Self {
+        Self::start_with(songscore, SongScoreBody::Plain).await
+    }
+
+    pub async fn start_with(songscore: &'static str, kind: SongScoreBody) -> Self {
+        let mut hosts = super::default_hosts();
+        hosts.push(OTHER_RELAY_HOST);
+        let (ca_cert, ca_key) = crate::os::generate_ca(&hosts);
+
+        let key_pair = KeyPair::generate().unwrap();
+        let cert =
+            CertificateParams::new(hosts.iter().map(ToString::to_string).collect::<Vec<_>>())
+                .unwrap()
+                .signed_by(&key_pair, &ca_cert, &ca_key)
+                .unwrap();
+
+        let mut server_config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![cert.der().clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der())),
+            )
+            .unwrap();
+        server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let service =
+                        hyper::service::service_fn(move |req| respond(req, songscore, kind));
+                    let _ = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        Self {
+            addr,
+            ca_der: ca_cert.der().clone(),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 信任模拟服务器证书的 TLS 客户端
+    pub fn tls_connector(&self) -> TlsConnector {
+        TlsConnector::from(Arc::new(self.tls_config()))
+    }
+
+    pub fn tls_config(&self) -> ClientConfig {
+        client_config(&self.ca_der)
+    }
+
+    /// 代理服务器使用的上游客户端，所有域名都会被解析到本模拟服务器上
+    pub fn client(&self) -> Client<impl Connect + Clone + use<>, hudsucker::Body> {
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(client_config(&self.ca_der))
+            .https_or_http()
+            .enable_http1()
+            .enable_http2()
+            .wrap_connector(LoopbackConnector(self.addr));
+
+        Client::builder(TokioExecutor::new()).build(https)
+    }
+}
+
+pub fn gzip(data: &[u8]) -> Bytes {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    Bytes::from(encoder.finish().unwrap())
+}
+
+async fn respond(
+    req: Request<Incoming>,
+    songscore: &'static str,
+    kind: SongScoreBody,
+) -> Result<Response<UnsyncBoxBody<Bytes, Infallible>>, Infallible> {
+    if req.method() != Method::POST || req.uri().path() != SONGSCORE_PATH {
+        let res = Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Full::new(Bytes::from_static(b"not found")).boxed_unsync());
+        return Ok(res.unwrap());
+    }
+
+    let res = Response::builder().header(header::CONTENT_TYPE, "application/json");
+    let songscore = Bytes::from_static(songscore.as_bytes());
+
+    let res = match kind {
+        SongScoreBody::Plain => res.body(Full::new(songscore).boxed_unsync()),
+        SongScoreBody::Gzip => res
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(Full::new(gzip(&songscore)).boxed_unsync()),
+        SongScoreBody::Stalled(delay) => {
+            let (first, rest) = songscore.split_at(songscore.len() / 2);
+            let (first, rest) = (songscore.slice_ref(first), songscore.slice_ref(rest));
+            let chunks = stream::iter([first]).chain(stream::once(async move {
+                tokio::time::sleep(delay).await;
+                rest
+            }));
+            res.body(StreamBody::new(chunks.map(|x| Ok(Frame::data(x)))).boxed_unsync())
+        }
+    };
+
+    Ok(res.unwrap())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LoopbackConnector(SocketAddr);
+
+impl tower_service::Service<Uri> for LoopbackConnector {
+    type Response = TokioIo<TcpStream>;
+    type Error = std::io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _dst: Uri) -> Self::Future {
+        let addr = self.0;
+        Box::pin(async move { Ok(TokioIo::new(TcpStream::connect(addr).await?)) })
+    }
+}
+
+/// 模拟用户原本使用的 HTTP 或 SOCKS5 代理，所有连接都会转发到指定地址，并记录请求连接的目标
+pub struct MockUpstream {
+    proxy: UpstreamProxy,
+    requested: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockUpstream {
+    pub async fn start(socks5: bool, target: SocketAddr) -> Self {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requested: Arc<Mutex<Vec<String>>> = Default::default();
+
+        let requested_clone = requested.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let requested = requested_clone.clone();
+                tokio::spawn(async move {
+                    let authority = if socks5 {
+                        accept_socks5(&mut stream).await
+                    } else {
+                        accept_http_connect(&mut stream).await
+                    };
+                    requested.lock().unwrap().push(authority);
+
+                    let mut server = TcpStream::connect(target).await.unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut stream, &mut server).await;
+                });
+            }
+        });
+
+        let host = Ipv4Addr::LOCALHOST.to_string();
+        let proxy = if socks5 {
+            UpstreamProxy::Socks5 { host, port }
+        } else {
+            UpstreamProxy::Http { host, port }
+        };
+
+        Self { proxy, requested }
+    }
+
+    pub fn proxy(&self) -> UpstreamProxy {
+        self.proxy.clone()
+    }
+
+    pub fn requested(&self) -> Vec<String> {
+        self.requested.lock().unwrap().clone()
+    }
+}
+
+async fn accept_http_connect(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    let head = String::from_utf8(head).unwrap();
+    let authority = head
+        .strip_prefix("CONNECT ")
+        .and_then(|x| x.split(' ').next())
+        .expect("上游代理只支持 CONNECT 请求")
+        .to_string();
+
+    stream
+        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+        .await
+        .unwrap();
+    authority
+}
+
+async fn accept_socks5(stream: &mut TcpStream) -> String {
+    let mut greeting = [0; 2];
+    stream.read_exact(&mut greeting).await.unwrap();
+    let mut methods = vec![0; greeting[1] as usize];
+    stream.read_exact(&mut methods).await.unwrap();
+    assert!(methods.contains(&0));
+    stream.write_all(&[5, 0]).await.unwrap();
+
+    let mut request = [0; 4];
+    stream.read_exact(&mut request).await.unwrap();
+    assert_eq!(request[..3], [5, 1, 0]);
+    let host = match request[3] {
+        1 => Ipv4Addr::from(stream.read_u32().await.unwrap()).to_string(),
+        3 => {
+            let mut host = vec![0; stream.read_u8().await.unwrap() as usize];
+            stream.read_exact(&mut host).await.unwrap();
+            String::from_utf8(host).unwrap()
+        }
+        other => panic!("意外的地址类型 {other}"),
+    };
+    let port = stream.read_u16().await.unwrap();
+
+    stream
+        .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+        .await
+        .unwrap();
+    format!("{host}:{port}")
+}
+
+/// 运行在随机端口上的 hudsucker 代理，使用 [`crate::Handler`] 处理请求
+pub struct TestProxy {
+    addr: SocketAddr,
+    ca_der: CertificateDer<'static>,
+    _finished_rx: Receiver<()>,
+}
+
+impl TestProxy {
+    pub async fn start(wahlap: &MockWahlap) -> Self {
+        Self::start_with_rules(wahlap, RuleSet::default()).await
+    }
+
+    pub async fn start_with_limits(wahlap: &MockWahlap, capture_limits: CaptureLimits) -> Self {
+        Self::start_with(
+            wahlap.client(),
+            RuleSet::default(),
+            None,
+            capture_limits,
+            None,
+        )
+        .await
+    }
+
+    pub async fn start_with_rules(wahlap: &MockWahlap, rules: RuleSet) -> Self {
+        Self::start_with(wahlap.client(), rules, None, CaptureLimits::default(), None).await
+    }
+
+    /// 把捕获记录和歌曲目录保存到指定的目录中
+    pub async fn start_with_data_dir(wahlap: &MockWahlap, rules: RuleSet, data_dir: &Path) -> Self {
+        Self::start_with(
+            wahlap.client(),
+            rules,
+            None,
+            CaptureLimits::default(),
+            Some(data_dir),
+        )
+        .await
+    }
+
+    /// 通过上游代理连接模拟服务器，上游代理需要把所有连接转发到模拟服务器上
+    pub async fn start_with_upstream(wahlap: &MockWahlap, upstream: &MockUpstream) -> Self {
+        let proxy = upstream.proxy();
+        let client = crate::upstream::client(Some(proxy.clone()), wahlap.tls_config());
+        Self::start_with(
+            client,
+            RuleSet::default(),
+            Some(proxy),
+            CaptureLimits::default(),
+            None,
+        )
+        .await
+    }
+
+    async fn start_with<C>(
+        client: Client<C, hudsucker::Body>,
+        rules: RuleSet,
+        upstream_proxy: Option<UpstreamProxy>,
+        capture_limits: CaptureLimits,
+        data_dir: Option<&Path>,
+    ) -> Self
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let (cert, key_pair) = crate::os::generate_ca(&rules.hosts());
+        let ca_der = cert.der().clone();
+        let ca = RcgenAuthority::new(key_pair, cert, 1000, default_provider());
+
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sx, finished_rx) = tokio::sync::mpsc::channel(1);
+
+        let proxy = hudsucker::Proxy::builder()
+            .with_listener(listener)
+            .with_ca(ca)
+            .with_client(client)
+            .with_http_handler(crate::Handler::new(
+                sx,
+                rules,
+                upstream_proxy,
+                None,
+                capture_limits,
+                data_dir,
+            ))
+            .build()
+            .unwrap();
+
+        tokio::spawn(proxy.start());
+
+        Self {
+            addr,
+            ca_der,
+            _finished_rx: finished_rx,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 信任代理生成的 CA 证书的 TLS 客户端
+    pub fn tls_connector(&self) -> TlsConnector {
+        TlsConnector::from(Arc::new(client_config(&self.ca_der)))
+    }
+
+    /// 向代理发送 CONNECT 请求，返回建立好的隧道
+    pub async fn connect(&self, host: &str, port: u16) -> TcpStream {
+        connect_via(self.addr, host, port).await
+    }
+
+    /// 通过 CONNECT 隧道经由代理发送 HTTPS 请求，并信任代理生成的 CA 证书
+    pub async fn request(&self, method: Method, url: &str) -> (StatusCode, Bytes) {
+        let uri: Uri = url.parse().unwrap();
+        let host = uri.host().unwrap();
+        let port = uri.port_u16().unwrap_or(443);
+
+        let stream = self.connect(host, port).await;
+
+        send_https(&self.tls_connector(), stream, method, url).await
+    }
+
+    /// 通过代理发送 HTTPS 请求，返回包含响应头的完整响应
+    pub async fn request_response(&self, method: Method, url: &str) -> Response<Bytes> {
+        let uri: Uri = url.parse().unwrap();
+        let stream = self
+            .connect(uri.host().unwrap(), uri.port_u16().unwrap_or(443))
+            .await;
+
+        send_https_response(&self.tls_connector(), stream, method, url).await
+    }
+
+    /// 在同一个 HTTP/2 连接上并发发送多个请求，响应按请求的顺序返回
+    pub async fn request_h2(&self, requests: &[(Method, &str)]) -> Vec<(StatusCode, Bytes)> {
+        let uri: Uri = requests[0].1.parse().unwrap();
+        let host = uri.host().unwrap().to_string();
+        let stream = self.connect(&host, uri.port_u16().unwrap_or(443)).await;
+
+        let mut config = client_config(&self.ca_der);
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let tls = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from(host).unwrap(), stream)
+            .await
+            .unwrap();
+        assert_eq!(tls.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        let (sender, conn) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(tls))
+                .await
+                .unwrap();
+        tokio::spawn(conn);
+
+        let mut tasks = Vec::with_capacity(requests.len());
+        for (method, url) in requests {
+            let mut sender = sender.clone();
+            let req = Request::builder()
+                .method(method)
+                .uri(*url)
+                .body(Full::new(Bytes::new()))
+                .unwrap();
+            tasks.push(tokio::spawn(async move {
+                let res = sender.send_request(req).await.unwrap();
+                let status = res.status();
+                (status, res.into_body().collect().await.unwrap().to_bytes())
+            }));
+        }
+
+        let mut responses = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            responses.push(task.await.unwrap());
+        }
+        responses
+    }
+}
+
+/// 向指定地址的代理发送 CONNECT 请求，返回建立好的隧道
+pub async fn connect_via(proxy_addr: SocketAddr, host: &str, port: u16) -> TcpStream {
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(
+            format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n\r\n").as_bytes(),
+        )
+        .await
+        .unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    assert!(head.starts_with(b"HTTP/1.1 200"), "代理拒绝了 CONNECT 请求");
+
+    stream
+}
+
+/// 不经过代理，直接向指定地址发送 HTTP GET 请求
+pub async fn get_direct(addr: SocketAddr, path: &str) -> Response<Bytes> {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+
+    let req = Request::builder()
+        .uri(path)
+        .header(header::HOST, addr.to_string())
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+
+    let (parts, body) = sender.send_request(req).await.unwrap().into_parts();
+    Response::from_parts(parts, body.collect().await.unwrap().to_bytes())
+}
+
+/// 在已经建立的 TCP 连接上完成 TLS 握手并发送一个 HTTP/1.1 请求
+pub async fn send_https(
+    connector: &TlsConnector,
+    stream: TcpStream,
+    method: Method,
+    url: &str,
+) -> (StatusCode, Bytes) {
+    let res = send_https_response(connector, stream, method, url).await;
+    (res.status(), res.into_body())
+}
+
+/// 和 [`send_https`] 相同，但是保留响应头
+pub async fn send_https_response(
+    connector: &TlsConnector,
+    stream: TcpStream,
+    method: Method,
+    url: &str,
+) -> Response<Bytes> {
+    let uri: Uri = url.parse().unwrap();
+    let host = uri.host().unwrap().to_string();
+
+    let tls = connector
+        .connect(ServerName::try_from(host.clone()).unwrap(), stream)
+        .await
+        .unwrap();
+
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(tls))
+        .await
+        .unwrap();
+    tokio::spawn(conn);
+
+    let req = Request::builder()
+        .method(method)
+        .uri(uri.path_and_query().unwrap().as_str())
+        .header(header::HOST, host)
+        .body(Full::new(Bytes::new()))
+        .unwrap();
+
+    let res = sender.send_request(req).await.unwrap();
+    let (parts, body) = res.into_parts();
+    let body = body.collect().await.unwrap().to_bytes();
+
+    Response::from_parts(parts, body)
+}
@@ -0,0 +1,267 @@
+//! 通过上游 HTTP 或 SOCKS5 代理连接服务器，用于原本就在使用 Clash 等代理软件的用户
+
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use http::Uri;
+use hudsucker::{
+    hyper_util::{
+        client::legacy::{Client, connect::Connect},
+        rt::{TokioExecutor, TokioIo},
+    },
+    rustls::{ClientConfig, crypto::ring::default_provider},
+};
+use hyper_rustls::ConfigBuilderExt;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// 上游代理返回的响应头最大长度
+const MAX_RESPONSE_HEAD_LEN: usize = 8 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpstreamProxy {
+    Http { host: String, port: u16 },
+    Socks5 { host: String, port: u16 },
+}
+
+impl FromStr for UpstreamProxy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, address) = s.split_once("://").unwrap_or(("http", s));
+        let address = address.trim_end_matches('/');
+
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| format!("代理地址缺少端口号：{s}"))?;
+        let port = port
+            .parse()
+            .map_err(|_| format!("代理地址的端口号无效：{s}"))?;
+        let host = host
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host);
+        if host.is_empty() {
+            return Err(format!("代理地址缺少主机名：{s}"));
+        }
+        let host = host.to_string();
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "http" => Ok(Self::Http { host, port }),
+            "socks5" | "socks5h" => Ok(Self::Socks5 { host, port }),
+            other => Err(format!("不支持的代理协议：{other}")),
+        }
+    }
+}
+
+impl fmt::Display for UpstreamProxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (scheme, host, port) = match self {
+            Self::Http { host, port } => ("http", host, port),
+            Self::Socks5 { host, port } => ("socks5", host, port),
+        };
+        write!(f, "{scheme}://{}", authority(host, *port))
+    }
+}
+
+fn authority(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+impl UpstreamProxy {
+    pub fn addr(&self) -> (&str, u16) {
+        match self {
+            Self::Http { host, port } | Self::Socks5 { host, port } => (host, *port),
+        }
+    }
+
+    /// 通过上游代理连接到目标服务器，返回的连接可以直接收发目标服务器的数据
+    pub async fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let (proxy_host, proxy_port) = self.addr();
+        let mut stream = TcpStream::connect((proxy_host, proxy_port)).await?;
+        stream.set_nodelay(true)?;
+
+        match self {
+            Self::Http { .. } => http_connect(&mut stream, host, port).await?,
+            Self::Socks5 { .. } => socks5_connect(&mut stream, host, port).await?,
+        }
+
+        Ok(stream)
+    }
+}
+
+fn proxy_error(message: impl Into<String>) -> io::Error {
+    io::Error::other(message.into())
+}
+
+async fn http_connect(stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+    let authority = authority(host, port);
+    stream
+        .write_all(format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n\r\n").as_bytes())
+        .await?;
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_RESPONSE_HEAD_LEN {
+            return Err(proxy_error("上游代理的响应头过长"));
+        }
+        head.push(stream.read_u8().await?);
+    }
+
+    let status_line = head.split(|x| *x == b'\r').next().unwrap_or_default();
+    let status = status_line.split(|x| *x == b' ').nth(1).unwrap_or_default();
+    if status != b"200" {
+        return Err(proxy_error(format!(
+            "上游代理拒绝了连接 {authority}：{}",
+            String::from_utf8_lossy(status_line)
+        )));
+    }
+
+    Ok(())
+}
+
+async fn socks5_connect(stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+    // 只支持无需认证的 SOCKS5 代理
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [5, 0] {
+        return Err(proxy_error("上游 SOCKS5 代理需要认证或不受支持"));
+    }
+
+    let mut request = vec![5, 1, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            let len = u8::try_from(host.len()).map_err(|_| proxy_error("域名过长"))?;
+            request.push(3);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 5 || reply[1] != 0 {
+        return Err(proxy_error(format!(
+            "上游 SOCKS5 代理拒绝了连接 {}，错误码 {}",
+            authority(host, port),
+            reply[1]
+        )));
+    }
+
+    // 跳过代理返回的绑定地址和端口
+    let addr_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        _ => return Err(proxy_error("上游 SOCKS5 代理返回了未知的地址类型")),
+    };
+    let mut bound = vec![0; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+/// 连接目标服务器的连接器，配置了上游代理时会通过上游代理连接
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamConnector {
+    proxy: Option<Arc<UpstreamProxy>>,
+}
+
+impl UpstreamConnector {
+    pub fn new(proxy: Option<UpstreamProxy>) -> Self {
+        Self {
+            proxy: proxy.map(Arc::new),
+        }
+    }
+}
+
+impl tower_service::Service<Uri> for UpstreamConnector {
+    type Response = TokioIo<TcpStream>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let proxy = self.proxy.clone();
+
+        Box::pin(async move {
+            let host = dst
+                .host()
+                .ok_or_else(|| proxy_error(format!("请求地址缺少主机名：{dst}")))?;
+            let host = host
+                .strip_prefix('[')
+                .and_then(|host| host.strip_suffix(']'))
+                .unwrap_or(host);
+            let port = dst.port_u16().unwrap_or(match dst.scheme_str() {
+                Some("https") => 443,
+                _ => 80,
+            });
+
+            let stream = match proxy {
+                Some(proxy) => proxy.connect(host, port).await?,
+                None => {
+                    let stream = TcpStream::connect((host, port)).await?;
+                    stream.set_nodelay(true)?;
+                    stream
+                }
+            };
+
+            Ok(TokioIo::new(stream))
+        })
+    }
+}
+
+/// 使用内置根证书的 TLS 配置，与 hudsucker 自带的 rustls 客户端一致
+pub fn tls_config() -> ClientConfig {
+    ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("无法创建 TLS 客户端配置")
+        .with_webpki_roots()
+        .with_no_client_auth()
+}
+
+/// 代理服务器向目标服务器发送请求时使用的客户端
+pub fn client(
+    proxy: Option<UpstreamProxy>,
+    tls_config: ClientConfig,
+) -> Client<impl Connect + Clone + use<>, hudsucker::Body> {
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http()
+        .enable_http1()
+        .enable_http2()
+        .wrap_connector(UpstreamConnector::new(proxy));
+
+    Client::builder(TokioExecutor::new())
+        .http1_title_case_headers(true)
+        .http1_preserve_header_case(true)
+        .build(https)
+}